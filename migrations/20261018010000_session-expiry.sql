@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE session_token
+    ADD COLUMN last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN expires_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP + INTERVAL '30 days',
+    ADD COLUMN ip_address TEXT,
+    ADD COLUMN user_agent TEXT;
+
+CREATE INDEX session_token_nonce_idx ON session_token(nonce);
@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+
+CREATE TABLE totp_recovery_code (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE invite_code (
+    id SERIAL PRIMARY KEY,
+    code TEXT NOT NULL UNIQUE,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    max_uses INTEGER NOT NULL CHECK (max_uses > 0),
+    uses INTEGER NOT NULL DEFAULT 0,
+    email TEXT,
+    expires_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+ALTER TABLE users ADD COLUMN invite_code_id INTEGER REFERENCES invite_code(id) ON DELETE SET NULL;
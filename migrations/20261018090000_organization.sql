@@ -0,0 +1,27 @@
+-- Add migration script here
+CREATE TABLE organization (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE organization_member (
+    organization_id INTEGER NOT NULL REFERENCES organization(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('owner', 'admin', 'member', 'viewer')),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX organization_member_user_id_idx ON organization_member (user_id);
+
+-- 組織のリソースは作成者が削除されても残るよう、作成者をNULLにします。
+ALTER TABLE server ADD COLUMN organization_id INTEGER REFERENCES organization(id) ON DELETE RESTRICT;
+ALTER TABLE server ALTER COLUMN author_id DROP NOT NULL;
+ALTER TABLE server DROP CONSTRAINT server_author_id_fkey;
+ALTER TABLE server ADD CONSTRAINT server_author_id_fkey FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL;
+
+ALTER TABLE setup_script ADD COLUMN organization_id INTEGER REFERENCES organization(id) ON DELETE CASCADE;
+ALTER TABLE setup_script ALTER COLUMN author_id DROP NOT NULL;
+ALTER TABLE setup_script DROP CONSTRAINT setup_script_author_id_fkey;
+ALTER TABLE setup_script ADD CONSTRAINT setup_script_author_id_fkey FOREIGN KEY (author_id) REFERENCES users(id) ON DELETE SET NULL;
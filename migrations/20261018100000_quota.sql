@@ -0,0 +1,24 @@
+-- Add migration script here
+CREATE TABLE quota_default (
+    scope TEXT PRIMARY KEY CHECK (scope IN ('user', 'organization')),
+    max_servers INTEGER NOT NULL,
+    max_cpu INTEGER NOT NULL,
+    max_memory INTEGER NOT NULL,
+    max_disk INTEGER NOT NULL
+);
+
+INSERT INTO quota_default (scope, max_servers, max_cpu, max_memory, max_disk) VALUES
+    ('user', 3, 4, 8192, 100),
+    ('organization', 10, 16, 32768, 500);
+
+-- NULLの項目は既定値を使います。
+CREATE TABLE quota_override (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    organization_id INTEGER UNIQUE REFERENCES organization(id) ON DELETE CASCADE,
+    max_servers INTEGER,
+    max_cpu INTEGER,
+    max_memory INTEGER,
+    max_disk INTEGER,
+    CHECK ((user_id IS NULL) <> (organization_id IS NULL))
+);
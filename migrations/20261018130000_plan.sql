@@ -0,0 +1,21 @@
+-- Add migration script here
+-- statusがactiveのプランのみ一覧に表示します。hiddenは一覧に表示しませんが作成はでき、retiredは新規作成できません。
+CREATE TABLE plan (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    cpu INTEGER NOT NULL,
+    -- メモリはMB、ディスクはGB、帯域はMbps、価格は円/月です。
+    memory INTEGER NOT NULL,
+    disk INTEGER NOT NULL,
+    bandwidth INTEGER NOT NULL,
+    price INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'hidden', 'retired')),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- これまでdata.jsonで定義していたプランです。既存のサーバーが参照しているためIDを維持します。
+INSERT INTO plan (id, name, cpu, memory, disk, bandwidth, price) VALUES
+    (0, 'micro', 1, 1024, 25, 100, 0);
+
+ALTER TABLE server
+    ADD CONSTRAINT server_plan_fkey FOREIGN KEY (plan) REFERENCES plan(id);
@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE ip_pool (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    cidr TEXT NOT NULL,
+    gateway TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- 割り当てに使わない範囲です。start_addressからend_addressまでを含みます。
+CREATE TABLE ip_reservation (
+    id SERIAL PRIMARY KEY,
+    pool_id INTEGER NOT NULL REFERENCES ip_pool(id) ON DELETE CASCADE,
+    start_address TEXT NOT NULL,
+    end_address TEXT NOT NULL,
+    note TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- server_idはサーバーの作成が終わるまでNULLです。サーバーを削除するとアドレスも解放されます。
+CREATE TABLE ip_allocation (
+    id SERIAL PRIMARY KEY,
+    pool_id INTEGER NOT NULL REFERENCES ip_pool(id),
+    address TEXT NOT NULL,
+    server_id TEXT UNIQUE REFERENCES server(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(pool_id, address)
+);
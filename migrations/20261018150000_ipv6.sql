@@ -0,0 +1,12 @@
+-- Add migration script here
+-- IPv6のネットワークを設定したプールでは、サーバーにIPv4とIPv6の両方のアドレスを割り当てます。
+ALTER TABLE ip_pool
+    ADD COLUMN cidr6 TEXT,
+    ADD COLUMN gateway6 TEXT;
+
+ALTER TABLE ip_allocation
+    ADD COLUMN family INTEGER NOT NULL DEFAULT 4 CHECK (family IN (4, 6));
+ALTER TABLE ip_allocation DROP CONSTRAINT ip_allocation_server_id_key;
+ALTER TABLE ip_allocation ADD CONSTRAINT ip_allocation_server_id_family_key UNIQUE (server_id, family);
+
+ALTER TABLE server ADD COLUMN ipv6_address TEXT UNIQUE;
@@ -0,0 +1,19 @@
+-- Add migration script here
+-- プールごとに、サーバーを接続するブリッジ、DNSサーバー、VLANとリージョンを設定します。
+ALTER TABLE ip_pool
+    ADD COLUMN interface TEXT,
+    ADD COLUMN dns_servers TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN vlan_id INTEGER CHECK (vlan_id BETWEEN 1 AND 4094),
+    ADD COLUMN region TEXT;
+
+-- 指定したプランのサーバーは、このプールからアドレスを割り当てます。
+ALTER TABLE plan
+    ADD COLUMN ip_pool_id INTEGER REFERENCES ip_pool(id) ON DELETE SET NULL;
+
+ALTER TABLE server
+    ADD COLUMN ip_pool_id INTEGER REFERENCES ip_pool(id);
+
+UPDATE server
+SET ip_pool_id = ip_allocation.pool_id
+FROM ip_allocation
+WHERE ip_allocation.server_id = server.id;
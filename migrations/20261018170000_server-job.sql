@@ -0,0 +1,22 @@
+-- Add migration script here
+-- サーバーはprovisioningで登録し、VMコントローラーで作成できたらrunning、諦めた場合はfailedにします。
+ALTER TABLE server
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'running' CHECK (status IN ('provisioning', 'running', 'failed'));
+
+-- VMコントローラーへの作成依頼を、バックグラウンドのワーカーが順に処理します。
+-- payloadには作成時に送る内容を保存し、パスワードを含むため処理が終わったら消します。
+CREATE TABLE server_job (
+    id SERIAL PRIMARY KEY,
+    server_id TEXT NOT NULL REFERENCES server(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('create')),
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'running', 'succeeded', 'failed')),
+    payload TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    -- pendingの場合は次に実行する時刻、runningの場合は処理中のワーカーが止まったとみなす時刻です。
+    run_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX server_job_run_at_idx ON server_job (run_at) WHERE status IN ('pending', 'running');
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- VMコントローラーが決めたサーバーのIDです。作成が終わるまではNULLです。
+ALTER TABLE server
+    ADD COLUMN domain_id TEXT UNIQUE;
+
+-- これまでのサーバーは、VMコントローラーのIDをそのままidにしています。
+UPDATE server
+SET domain_id = id
+WHERE status <> 'provisioning';
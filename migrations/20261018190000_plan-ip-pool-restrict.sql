@@ -0,0 +1,5 @@
+-- Add migration script here
+-- プランが使っているプールを削除した場合に、既定のプールへ黙って切り替わらないようにします。
+ALTER TABLE plan
+    DROP CONSTRAINT plan_ip_pool_id_fkey,
+    ADD CONSTRAINT plan_ip_pool_id_fkey FOREIGN KEY (ip_pool_id) REFERENCES ip_pool(id) ON DELETE RESTRICT;
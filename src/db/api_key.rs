@@ -0,0 +1,136 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+// APIキーを作成します。有効期限が過去の場合は作成せずにNoneを返します。
+// 有効期限は、認証時と同じくデータベースの時刻と比べます。
+pub async fn add_api_key(
+    pool: &PgPool,
+    user_id: i32,
+    name: String,
+    key_hash: String,
+    scopes: Vec<String>,
+    expires_at: Option<NaiveDateTime>,
+) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            api_key (user_id, name, key_hash, scopes, expires_at)
+        SELECT
+            $1, $2, $3, $4, $5
+        WHERE
+            $5::TIMESTAMP IS NULL OR $5::TIMESTAMP > CURRENT_TIMESTAMP
+        RETURNING id
+        "#,
+        user_id,
+        name,
+        key_hash,
+        &scopes,
+        expires_at
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+// 有効なAPIキーであれば最終利用日時を更新し、スコープを返します。
+pub async fn touch_api_key(
+    pool: &PgPool,
+    key_hash: String,
+    user_id: i32,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            api_key
+        SET
+            last_used_at = CURRENT_TIMESTAMP
+        FROM
+            users
+        WHERE
+            users.id = api_key.user_id
+        AND
+            users.suspended_at IS NULL
+        AND
+            key_hash = $1
+        AND
+            user_id = $2
+        AND
+            (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        RETURNING
+            api_key.scopes
+        "#,
+        key_hash,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.scopes))
+}
+
+pub async fn get_api_keys_by_user(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<
+    Vec<(
+        i32,
+        String,
+        Vec<String>,
+        Option<NaiveDateTime>,
+        Option<NaiveDateTime>,
+        Option<NaiveDateTime>,
+    )>,
+> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            scopes,
+            expires_at,
+            last_used_at,
+            created_at
+        FROM
+            api_key
+        WHERE
+            user_id = $1
+        ORDER BY
+            id
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.name,
+            row.scopes,
+            row.expires_at,
+            row.last_used_at,
+            row.created_at,
+        )
+    })
+    .collect();
+    Ok(keys)
+}
+
+pub async fn delete_api_key(pool: &PgPool, key_id: i32, user_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            api_key
+        WHERE
+            id = $1
+        AND
+            user_id = $2
+        RETURNING
+            id
+        "#,
+        key_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
@@ -0,0 +1,155 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+pub struct NewAuditLog<'a> {
+    pub actor_id: Option<i32>,
+    pub action: &'a str,
+    pub target: Option<String>,
+    pub outcome: &'a str,
+    pub reason: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditLog {
+    pub id: i64,
+    pub actor_id: Option<i32>,
+    pub action: String,
+    pub target: Option<String>,
+    pub outcome: String,
+    pub reason: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// 管理者向けの検索条件です。指定しなかった条件では絞り込みません。
+#[derive(Deserialize)]
+pub struct AuditLogFilter {
+    pub actor_id: Option<i32>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<String>,
+    pub ip_address: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+pub async fn add_audit_log(pool: &PgPool, entry: NewAuditLog<'_>) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            audit_log (actor_id, action, target, outcome, reason, ip_address, user_agent)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        entry.actor_id,
+        entry.action,
+        entry.target,
+        entry.outcome,
+        entry.reason,
+        entry.ip_address,
+        entry.user_agent
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// ユーザーが行った操作と、ユーザーのアカウントに対する操作を新しい順に取得します。
+// beforeを指定した場合は、そのIDより古いものを取得します。
+pub async fn get_audit_logs_by_user(
+    pool: &PgPool,
+    user_id: i32,
+    before: Option<i64>,
+    limit: i64,
+) -> anyhow::Result<Vec<AuditLog>> {
+    let logs = sqlx::query_as!(
+        AuditLog,
+        r#"
+        SELECT
+            id,
+            actor_id,
+            action,
+            target,
+            outcome,
+            reason,
+            ip_address,
+            user_agent,
+            created_at
+        FROM
+            audit_log
+        WHERE
+            (actor_id = $1 OR target = 'user:' || $1)
+        AND
+            ($2::BIGINT IS NULL OR id < $2)
+        ORDER BY
+            id DESC
+        LIMIT $3
+        "#,
+        user_id,
+        before,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(logs)
+}
+
+pub async fn search_audit_logs(
+    pool: &PgPool,
+    filter: &AuditLogFilter,
+    before: Option<i64>,
+    limit: i64,
+) -> anyhow::Result<Vec<AuditLog>> {
+    let logs = sqlx::query_as!(
+        AuditLog,
+        r#"
+        SELECT
+            id,
+            actor_id,
+            action,
+            target,
+            outcome,
+            reason,
+            ip_address,
+            user_agent,
+            created_at
+        FROM
+            audit_log
+        WHERE
+            ($1::BIGINT IS NULL OR id < $1)
+        AND
+            ($2::INTEGER IS NULL OR actor_id = $2)
+        AND
+            ($3::TEXT IS NULL OR action = $3)
+        AND
+            ($4::TEXT IS NULL OR target = $4)
+        AND
+            ($5::TEXT IS NULL OR outcome = $5)
+        AND
+            ($6::TEXT IS NULL OR ip_address = $6)
+        AND
+            ($7::TIMESTAMP IS NULL OR created_at >= $7)
+        AND
+            ($8::TIMESTAMP IS NULL OR created_at < $8)
+        ORDER BY
+            id DESC
+        LIMIT $9
+        "#,
+        before,
+        filter.actor_id,
+        filter.action,
+        filter.target,
+        filter.outcome,
+        filter.ip_address,
+        filter.since,
+        filter.until,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(logs)
+}
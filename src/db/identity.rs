@@ -0,0 +1,52 @@
+use sqlx::PgPool;
+
+// 外部のIDプロバイダーのアカウントに紐づいたユーザーのIDと、停止されているかを返します。
+pub async fn get_user_by_identity(
+    pool: &PgPool,
+    issuer: String,
+    subject: String,
+) -> anyhow::Result<Option<(i32, bool)>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            users.id,
+            users.suspended_at IS NOT NULL AS "suspended!"
+        FROM
+            user_identity
+        JOIN
+            users ON users.id = user_identity.user_id
+        WHERE
+            user_identity.issuer = $1
+        AND
+            user_identity.subject = $2
+        "#,
+        issuer,
+        subject
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.map(|r| (r.id, r.suspended)))
+}
+
+pub async fn add_identity(
+    pool: &PgPool,
+    user_id: i32,
+    issuer: String,
+    subject: String,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            user_identity (user_id, issuer, subject)
+        VALUES
+            ($1, $2, $3)
+        "#,
+        user_id,
+        issuer,
+        subject
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
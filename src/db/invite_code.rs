@@ -0,0 +1,216 @@
+use chrono::NaiveDateTime;
+use sqlx::{PgConnection, PgPool};
+
+// 作成できる招待コードの数を数える期間の秒数です。
+const INVITE_CODE_LIMIT_PERIOD: f64 = 30.0 * 24.0 * 3600.0;
+
+// 招待コードを作成します。max_codesを指定した場合は、期間内に作成したコードが
+// その数に達していればNoneを返します。
+pub async fn add_invite_code(
+    pool: &PgPool,
+    code: String,
+    created_by: i32,
+    max_uses: i32,
+    email: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    max_codes: Option<i64>,
+) -> anyhow::Result<Option<i32>> {
+    let mut tx = pool.begin().await?;
+    if let Some(max_codes) = max_codes {
+        // 同じユーザーが同時に作成しても上限を超えないよう、ユーザーごとにロックを取ります。
+        sqlx::query!(
+            r#"
+            SELECT
+                pg_advisory_xact_lock(hashtext('invite_code'), $1)
+            "#,
+            created_by
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let rec = sqlx::query!(
+            r#"
+            SELECT
+                count(*) AS "count!"
+            FROM
+                invite_code
+            WHERE
+                created_by = $1
+            AND
+                created_at > CURRENT_TIMESTAMP - make_interval(secs => $2)
+            "#,
+            created_by,
+            INVITE_CODE_LIMIT_PERIOD
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if rec.count >= max_codes {
+            return Ok(None);
+        }
+    }
+    let r = sqlx::query!(
+        r#"
+        INSERT INTO
+            invite_code (code, created_by, max_uses, email, expires_at)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        code,
+        created_by,
+        max_uses,
+        email,
+        expires_at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(r.id))
+}
+
+// 招待コードが使える状態であれば、そのIDを返します。使用回数は本登録のときに増やします。
+pub async fn find_invite_code(
+    pool: &PgPool,
+    code: String,
+    email: String,
+) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            id
+        FROM
+            invite_code
+        WHERE
+            code = $1
+        AND
+            uses < max_uses
+        AND
+            revoked_at IS NULL
+        AND
+            (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        AND
+            (email IS NULL OR lower(email) = lower($2))
+        "#,
+        code,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+// 招待コードがまだ使える状態であれば使用回数を1つ増やします。使えない場合はfalseを返します。
+pub async fn consume_invite_code(
+    conn: &mut PgConnection,
+    invite_code_id: i32,
+    email: &str,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            invite_code
+        SET
+            uses = uses + 1
+        WHERE
+            id = $1
+        AND
+            uses < max_uses
+        AND
+            revoked_at IS NULL
+        AND
+            (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        AND
+            (email IS NULL OR lower(email) = lower($2))
+        RETURNING
+            id
+        "#,
+        invite_code_id,
+        email
+    )
+    .fetch_optional(conn)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn get_invite_codes(
+    pool: &PgPool,
+    created_by: Option<i32>,
+) -> anyhow::Result<
+    Vec<(
+        i32,
+        String,
+        Option<i32>,
+        i32,
+        i32,
+        Option<String>,
+        Option<NaiveDateTime>,
+        Option<NaiveDateTime>,
+        Option<NaiveDateTime>,
+    )>,
+> {
+    let codes = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            code,
+            created_by,
+            max_uses,
+            uses,
+            email,
+            expires_at,
+            revoked_at,
+            created_at
+        FROM
+            invite_code
+        WHERE
+            ($1::INTEGER IS NULL OR created_by = $1)
+        ORDER BY
+            id
+        "#,
+        created_by
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.code,
+            row.created_by,
+            row.max_uses,
+            row.uses,
+            row.email,
+            row.expires_at,
+            row.revoked_at,
+            row.created_at,
+        )
+    })
+    .collect();
+    Ok(codes)
+}
+
+// 招待コードを無効にします。created_byがNoneの場合は作成者を問いません。
+pub async fn revoke_invite_code(
+    pool: &PgPool,
+    invite_code_id: i32,
+    created_by: Option<i32>,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            invite_code
+        SET
+            revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP)
+        WHERE
+            id = $1
+        AND
+            ($2::INTEGER IS NULL OR created_by = $2)
+        RETURNING
+            id
+        "#,
+        invite_code_id,
+        created_by
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
@@ -0,0 +1,614 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+use crate::utils::ipam::{IpPoolNetwork, IpReservationRange};
+
+// 作成が終わらないまま残った割り当てを解放するまでの秒数です。
+const PENDING_ALLOCATION_TTL: f64 = 3600.0;
+
+// 登録するプールの設定です。
+pub struct IpPoolSpec {
+    pub name: String,
+    pub cidr: String,
+    pub gateway: String,
+    pub cidr6: Option<String>,
+    pub gateway6: Option<String>,
+    pub interface: String,
+    pub dns_servers: Vec<String>,
+    pub vlan_id: Option<i32>,
+    pub region: Option<String>,
+}
+
+// プールがまだなければ登録し、プールに割り当てが記録されていない既存のサーバーのアドレスを記録します。
+// 既に登録されている場合は、未設定の項目のみ設定します。
+pub async fn ensure_ip_pool(pool: &PgPool, spec: IpPoolSpec) -> anyhow::Result<i32> {
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            ip_pool (name, cidr, gateway, cidr6, gateway6, interface, dns_servers, vlan_id, region)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (name) DO UPDATE SET
+            cidr6 = COALESCE(ip_pool.cidr6, EXCLUDED.cidr6),
+            gateway6 = COALESCE(ip_pool.gateway6, EXCLUDED.gateway6),
+            interface = COALESCE(ip_pool.interface, EXCLUDED.interface),
+            dns_servers = CASE
+                WHEN cardinality(ip_pool.dns_servers) = 0 THEN EXCLUDED.dns_servers
+                ELSE ip_pool.dns_servers
+            END
+        RETURNING id
+        "#,
+        spec.name,
+        spec.cidr,
+        spec.gateway,
+        spec.cidr6,
+        spec.gateway6,
+        spec.interface,
+        &spec.dns_servers,
+        spec.vlan_id,
+        spec.region
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            ip_allocation (pool_id, address, server_id, family)
+        SELECT
+            $1,
+            split_part(address.value, '/', 1),
+            server.id,
+            address.family
+        FROM
+            server
+        CROSS JOIN LATERAL (
+            VALUES (server.ip_address, 4), (server.ipv6_address, 6)
+        ) AS address (value, family)
+        WHERE
+            address.value IS NOT NULL
+        AND
+            server.ip_pool_id IS NULL
+        AND
+            NOT EXISTS (
+                SELECT 1 FROM ip_allocation
+                WHERE ip_allocation.server_id = server.id
+                AND ip_allocation.family = address.family
+            )
+        ON CONFLICT DO NOTHING
+        "#,
+        rec.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE
+            server
+        SET
+            ip_pool_id = ip_allocation.pool_id
+        FROM
+            ip_allocation
+        WHERE
+            ip_allocation.server_id = server.id
+        AND
+            server.ip_pool_id IS NULL
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(rec.id)
+}
+
+// プールを登録します。同じ名前のプールがある場合はNoneを返します。
+pub async fn add_ip_pool(pool: &PgPool, spec: IpPoolSpec) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            ip_pool (name, cidr, gateway, cidr6, gateway6, interface, dns_servers, vlan_id, region)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        "#,
+        spec.name,
+        spec.cidr,
+        spec.gateway,
+        spec.cidr6,
+        spec.gateway6,
+        spec.interface,
+        &spec.dns_servers,
+        spec.vlan_id,
+        spec.region
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+// プールの接続先の設定を更新します。アドレスの範囲は割り当てと食い違わないよう変更できません。
+// 存在しない場合はNone、同じ名前のプールがある場合はSome(false)を返します。
+pub async fn set_ip_pool(
+    pool: &PgPool,
+    pool_id: i32,
+    name: String,
+    interface: String,
+    dns_servers: Vec<String>,
+    vlan_id: Option<i32>,
+    region: Option<String>,
+) -> anyhow::Result<Option<bool>> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            ip_pool
+        SET
+            name = $2,
+            interface = $3,
+            dns_servers = $4,
+            vlan_id = $5,
+            region = $6
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        pool_id,
+        name,
+        interface,
+        &dns_servers,
+        vlan_id,
+        region
+    )
+    .fetch_optional(pool)
+    .await;
+    match result {
+        Ok(rec) => Ok(rec.map(|_| true)),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(Some(false)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// プールを削除した結果です。
+pub enum IpPoolDeletion {
+    Deleted,
+    NotFound,
+    // プランが指定しているプールは削除できません。
+    UsedByPlan,
+    // アドレスを割り当てたサーバーが残っているプールは削除できません。
+    UsedByServer,
+}
+
+// プールを削除します。プランやサーバーが使っている場合は削除せず、その理由を返します。
+pub async fn delete_ip_pool(pool: &PgPool, pool_id: i32) -> anyhow::Result<IpPoolDeletion> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM
+            ip_pool
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        pool_id
+    )
+    .fetch_optional(pool)
+    .await;
+    match result {
+        Ok(Some(_)) => Ok(IpPoolDeletion::Deleted),
+        Ok(None) => Ok(IpPoolDeletion::NotFound),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            if e.constraint() == Some("plan_ip_pool_id_fkey") {
+                Ok(IpPoolDeletion::UsedByPlan)
+            } else {
+                Ok(IpPoolDeletion::UsedByServer)
+            }
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn get_ip_pool_id(pool: &PgPool, name: &str) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            id
+        FROM
+            ip_pool
+        WHERE
+            name = $1
+        "#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+// プランに指定されたプールと、リージョンで絞り込んだプールを取得します。
+pub async fn get_ip_pool_ids(
+    pool: &PgPool,
+    pool_id: Option<i32>,
+    region: Option<&str>,
+) -> anyhow::Result<Vec<i32>> {
+    let ids = sqlx::query!(
+        r#"
+        SELECT
+            id
+        FROM
+            ip_pool
+        WHERE
+            ($1::INTEGER IS NULL OR id = $1)
+        AND
+            ($2::TEXT IS NULL OR region = $2)
+        ORDER BY
+            id
+        "#,
+        pool_id,
+        region
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.id)
+    .collect();
+    Ok(ids)
+}
+
+pub async fn get_regions(pool: &PgPool) -> anyhow::Result<Vec<String>> {
+    let regions = sqlx::query!(
+        r#"
+        SELECT DISTINCT
+            region AS "region!"
+        FROM
+            ip_pool
+        WHERE
+            region IS NOT NULL
+        ORDER BY
+            region
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.region)
+    .collect();
+    Ok(regions)
+}
+
+// プールのIPv4とIPv6のネットワークを取得します。
+pub async fn get_ip_pool(
+    pool: &PgPool,
+    pool_id: i32,
+) -> anyhow::Result<Option<(String, Option<String>)>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            cidr,
+            cidr6
+        FROM
+            ip_pool
+        WHERE
+            id = $1
+        "#,
+        pool_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| (r.cidr, r.cidr6)))
+}
+
+pub struct IpPoolUsage {
+    pub id: i32,
+    pub name: String,
+    pub cidr: String,
+    pub gateway: String,
+    pub cidr6: Option<String>,
+    pub gateway6: Option<String>,
+    pub interface: Option<String>,
+    pub dns_servers: Vec<String>,
+    pub vlan_id: Option<i32>,
+    pub region: Option<String>,
+    pub allocated: Vec<String>,
+    pub reservations: Vec<(String, String)>,
+}
+
+// プールの一覧と、それぞれの割り当て済みのアドレスと予約範囲を取得します。
+pub async fn get_ip_pools(pool: &PgPool) -> anyhow::Result<Vec<IpPoolUsage>> {
+    let pools = sqlx::query!(
+        r#"
+        SELECT
+            ip_pool.id,
+            ip_pool.name,
+            ip_pool.cidr,
+            ip_pool.gateway,
+            ip_pool.cidr6,
+            ip_pool.gateway6,
+            ip_pool.interface,
+            ip_pool.dns_servers,
+            ip_pool.vlan_id,
+            ip_pool.region,
+            ARRAY(
+                SELECT address FROM ip_allocation WHERE ip_allocation.pool_id = ip_pool.id
+            ) AS "allocated!",
+            ARRAY(
+                SELECT start_address FROM ip_reservation
+                WHERE ip_reservation.pool_id = ip_pool.id ORDER BY id
+            ) AS "reservation_starts!",
+            ARRAY(
+                SELECT end_address FROM ip_reservation
+                WHERE ip_reservation.pool_id = ip_pool.id ORDER BY id
+            ) AS "reservation_ends!"
+        FROM
+            ip_pool
+        ORDER BY
+            ip_pool.id
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| IpPoolUsage {
+        id: row.id,
+        name: row.name,
+        cidr: row.cidr,
+        gateway: row.gateway,
+        cidr6: row.cidr6,
+        gateway6: row.gateway6,
+        interface: row.interface,
+        dns_servers: row.dns_servers,
+        vlan_id: row.vlan_id,
+        region: row.region,
+        allocated: row.allocated,
+        reservations: row
+            .reservation_starts
+            .into_iter()
+            .zip(row.reservation_ends)
+            .collect(),
+    })
+    .collect();
+    Ok(pools)
+}
+
+// サーバーに割り当てたアドレスです。アドレスにはプレフィックス長を付けています。
+pub struct AllocatedAddresses {
+    pub pool_id: i32,
+    pub allocation_ids: Vec<i32>,
+    pub address: String,
+    pub gateway: String,
+    pub ipv6_address: Option<String>,
+    pub ipv6_gateway: Option<String>,
+    pub interface: String,
+    pub dns_servers: Vec<String>,
+    pub vlan_id: Option<i32>,
+}
+
+// プールの行をロックしてから、空いている最初のアドレスを割り当てます。
+// 同じプールへの割り当ては順番に処理されるため、同時に作成しても同じアドレスにはなりません。
+// IPv6のネットワークが設定されているプールでは、IPv6のアドレスも割り当てます。
+// 空きがない場合はNoneを返します。
+pub async fn allocate_ip_address(
+    pool: &PgPool,
+    pool_id: i32,
+) -> anyhow::Result<Option<AllocatedAddresses>> {
+    let mut tx = pool.begin().await?;
+    let ip_pool = sqlx::query!(
+        r#"
+        SELECT
+            cidr,
+            gateway,
+            cidr6,
+            gateway6,
+            interface,
+            dns_servers,
+            vlan_id
+        FROM
+            ip_pool
+        WHERE
+            id = $1
+        FOR UPDATE
+        "#,
+        pool_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            ip_allocation
+        WHERE
+            pool_id = $1
+        AND
+            server_id IS NULL
+        AND
+            created_at < CURRENT_TIMESTAMP - make_interval(secs => $2)
+        "#,
+        pool_id,
+        PENDING_ALLOCATION_TTL
+    )
+    .execute(&mut *tx)
+    .await?;
+    let allocated: HashSet<IpAddr> = sqlx::query!(
+        r#"
+        SELECT
+            address
+        FROM
+            ip_allocation
+        WHERE
+            pool_id = $1
+        "#,
+        pool_id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .filter_map(|row| row.address.parse().ok())
+    .collect();
+    let reservations: Vec<IpReservationRange> = sqlx::query!(
+        r#"
+        SELECT
+            start_address,
+            end_address
+        FROM
+            ip_reservation
+        WHERE
+            pool_id = $1
+        "#,
+        pool_id
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .filter_map(|row| IpReservationRange::parse(&row.start_address, &row.end_address))
+    .collect();
+
+    let interface = ip_pool
+        .interface
+        .ok_or_else(|| anyhow::anyhow!("IP pool {pool_id} has no interface"))?;
+    let mut networks = vec![(4, IpPoolNetwork::parse(&ip_pool.cidr, &ip_pool.gateway)?)];
+    if let (Some(cidr6), Some(gateway6)) = (&ip_pool.cidr6, &ip_pool.gateway6) {
+        networks.push((6, IpPoolNetwork::parse(cidr6, gateway6)?));
+    }
+    let mut allocation_ids = Vec::new();
+    let mut addresses = Vec::new();
+    for (family, network) in networks {
+        let Some(address) = network.find_free(&allocated, &reservations) else {
+            return Ok(None);
+        };
+        let rec = sqlx::query!(
+            r#"
+            INSERT INTO
+                ip_allocation (pool_id, address, family)
+            VALUES
+                ($1, $2, $3)
+            RETURNING id
+            "#,
+            pool_id,
+            address.to_string(),
+            family
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        allocation_ids.push(rec.id);
+        addresses.push(network.net.with_prefix(address));
+    }
+    tx.commit().await?;
+    let mut addresses = addresses.into_iter();
+    Ok(Some(AllocatedAddresses {
+        pool_id,
+        allocation_ids,
+        address: addresses.next().unwrap_or_default(),
+        gateway: ip_pool.gateway,
+        ipv6_address: addresses.next(),
+        ipv6_gateway: ip_pool.gateway6,
+        interface,
+        dns_servers: ip_pool.dns_servers,
+        vlan_id: ip_pool.vlan_id,
+    }))
+}
+
+// サーバーの作成に失敗した場合に、割り当てたアドレスを解放します。
+pub async fn release_ip_allocation(pool: &PgPool, allocation_ids: &[i32]) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            ip_allocation
+        WHERE
+            id = ANY($1)
+        AND
+            server_id IS NULL
+        "#,
+        allocation_ids
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_ip_reservations(
+    pool: &PgPool,
+    pool_id: i32,
+) -> anyhow::Result<Vec<(i32, String, String, Option<String>, Option<NaiveDateTime>)>> {
+    let reservations = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            start_address,
+            end_address,
+            note,
+            created_at
+        FROM
+            ip_reservation
+        WHERE
+            pool_id = $1
+        ORDER BY
+            id
+        "#,
+        pool_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.start_address,
+            row.end_address,
+            row.note,
+            row.created_at,
+        )
+    })
+    .collect();
+    Ok(reservations)
+}
+
+pub async fn add_ip_reservation(
+    pool: &PgPool,
+    pool_id: i32,
+    start_address: String,
+    end_address: String,
+    note: Option<String>,
+) -> anyhow::Result<i32> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            ip_reservation (pool_id, start_address, end_address, note)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        pool_id,
+        start_address,
+        end_address,
+        note
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec.id)
+}
+
+pub async fn delete_ip_reservation(
+    pool: &PgPool,
+    pool_id: i32,
+    reservation_id: i32,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            ip_reservation
+        WHERE
+            id = $1
+        AND
+            pool_id = $2
+        RETURNING
+            id
+        "#,
+        reservation_id,
+        pool_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
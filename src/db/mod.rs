@@ -1,4 +1,16 @@
+pub mod api_key;
+pub mod audit_log;
+pub mod identity;
+pub mod invite_code;
+pub mod ipam;
+pub mod organization;
+pub mod plan;
+pub mod quota;
 pub mod server;
+pub mod server_job;
 pub mod setup_script;
+pub mod ssh_key;
 pub mod token;
+pub mod totp;
 pub mod user;
+pub mod webauthn;
@@ -0,0 +1,286 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+use crate::token::ORG_ROLE_OWNER;
+
+// 組織を作成し、作成したユーザーをオーナーとして登録します。
+pub async fn add_organization(pool: &PgPool, name: String, owner_id: i32) -> anyhow::Result<i32> {
+    let mut tx = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        INSERT INTO
+            organization (name)
+        VALUES
+            ($1)
+        RETURNING id
+        "#,
+        name
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            organization_member (organization_id, user_id, role)
+        VALUES
+            ($1, $2, $3)
+        "#,
+        r.id,
+        owner_id,
+        ORG_ROLE_OWNER
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(r.id)
+}
+
+pub async fn get_organization(
+    pool: &PgPool,
+    organization_id: i32,
+) -> anyhow::Result<Option<(String, Option<NaiveDateTime>)>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            name,
+            created_at
+        FROM
+            organization
+        WHERE
+            id = $1
+        "#,
+        organization_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| (r.name, r.created_at)))
+}
+
+// ユーザーが所属している組織と、その組織でのロールを取得します。
+pub async fn get_organizations_by_user(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<Vec<(i32, String, String, Option<NaiveDateTime>)>> {
+    let organizations = sqlx::query!(
+        r#"
+        SELECT
+            organization.id,
+            organization.name,
+            organization_member.role,
+            organization.created_at
+        FROM
+            organization
+        JOIN
+            organization_member ON organization_member.organization_id = organization.id
+        WHERE
+            organization_member.user_id = $1
+        ORDER BY
+            organization.id
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.name, row.role, row.created_at))
+    .collect();
+    Ok(organizations)
+}
+
+pub async fn delete_organization(pool: &PgPool, organization_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            organization
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        organization_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn get_member_role(
+    pool: &PgPool,
+    organization_id: i32,
+    user_id: i32,
+) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            role
+        FROM
+            organization_member
+        WHERE
+            organization_id = $1
+        AND
+            user_id = $2
+        "#,
+        organization_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.role))
+}
+
+pub async fn get_members(
+    pool: &PgPool,
+    organization_id: i32,
+) -> anyhow::Result<Vec<(i32, String, String, String, Option<NaiveDateTime>)>> {
+    let members = sqlx::query!(
+        r#"
+        SELECT
+            users.id,
+            users.username,
+            users.email,
+            organization_member.role,
+            organization_member.created_at
+        FROM
+            organization_member
+        JOIN
+            users ON users.id = organization_member.user_id
+        WHERE
+            organization_member.organization_id = $1
+        ORDER BY
+            organization_member.created_at
+        "#,
+        organization_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.username, row.email, row.role, row.created_at))
+    .collect();
+    Ok(members)
+}
+
+// メンバーを追加します。既に所属している場合はfalseを返します。
+pub async fn add_member(
+    pool: &PgPool,
+    organization_id: i32,
+    user_id: i32,
+    role: String,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            organization_member (organization_id, user_id, role)
+        VALUES
+            ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        RETURNING
+            user_id
+        "#,
+        organization_id,
+        user_id,
+        role
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn set_member_role(
+    pool: &PgPool,
+    organization_id: i32,
+    user_id: i32,
+    role: String,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            organization_member
+        SET
+            role = $3
+        WHERE
+            organization_id = $1
+        AND
+            user_id = $2
+        RETURNING
+            user_id
+        "#,
+        organization_id,
+        user_id,
+        role
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn delete_member(
+    pool: &PgPool,
+    organization_id: i32,
+    user_id: i32,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            organization_member
+        WHERE
+            organization_id = $1
+        AND
+            user_id = $2
+        RETURNING
+            user_id
+        "#,
+        organization_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn count_owners(pool: &PgPool, organization_id: i32) -> anyhow::Result<i64> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            count(*)
+        FROM
+            organization_member
+        WHERE
+            organization_id = $1
+        AND
+            role = $2
+        "#,
+        organization_id,
+        ORG_ROLE_OWNER
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec.count.unwrap_or_default())
+}
+
+// ユーザーが唯一のオーナーになっている組織の数を取得します。
+pub async fn count_sole_owned_organizations(pool: &PgPool, user_id: i32) -> anyhow::Result<i64> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            count(*) AS "count!"
+        FROM
+            organization_member
+        WHERE
+            user_id = $1
+        AND
+            role = $2
+        AND
+            NOT EXISTS (
+                SELECT 1 FROM organization_member AS other
+                WHERE other.organization_id = organization_member.organization_id
+                AND other.user_id <> $1
+                AND other.role = $2
+            )
+        "#,
+        user_id,
+        ORG_ROLE_OWNER
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec.count)
+}
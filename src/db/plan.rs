@@ -0,0 +1,173 @@
+use sqlx::PgPool;
+
+pub const PLAN_STATUS_ACTIVE: &str = "active";
+pub const PLAN_STATUS_HIDDEN: &str = "hidden";
+pub const PLAN_STATUS_RETIRED: &str = "retired";
+
+pub const PLAN_STATUSES: &[&str] = &[PLAN_STATUS_ACTIVE, PLAN_STATUS_HIDDEN, PLAN_STATUS_RETIRED];
+
+pub struct Plan {
+    pub id: i32,
+    pub name: String,
+    pub cpu: i32,
+    pub memory: i32,
+    pub disk: i32,
+    pub bandwidth: i32,
+    pub price: i32,
+    pub status: String,
+    // 指定した場合は、このプールからアドレスを割り当てます。
+    pub ip_pool_id: Option<i32>,
+}
+
+// 追加・更新するプランの内容です。
+pub struct PlanSpec {
+    pub name: String,
+    pub cpu: i32,
+    pub memory: i32,
+    pub disk: i32,
+    pub bandwidth: i32,
+    pub price: i32,
+    pub status: String,
+    pub ip_pool_id: Option<i32>,
+}
+
+// すべてのプランを取得します。statusを指定した場合はそのプランのみ取得します。
+pub async fn get_plans(pool: &PgPool, status: Option<&str>) -> anyhow::Result<Vec<Plan>> {
+    let plans = sqlx::query_as!(
+        Plan,
+        r#"
+        SELECT
+            id,
+            name,
+            cpu,
+            memory,
+            disk,
+            bandwidth,
+            price,
+            status,
+            ip_pool_id
+        FROM
+            plan
+        WHERE
+            ($1::TEXT IS NULL OR status = $1)
+        ORDER BY
+            id
+        "#,
+        status
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(plans)
+}
+
+pub async fn get_plan(pool: &PgPool, plan_id: i32) -> anyhow::Result<Option<Plan>> {
+    let plan = sqlx::query_as!(
+        Plan,
+        r#"
+        SELECT
+            id,
+            name,
+            cpu,
+            memory,
+            disk,
+            bandwidth,
+            price,
+            status,
+            ip_pool_id
+        FROM
+            plan
+        WHERE
+            id = $1
+        "#,
+        plan_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(plan)
+}
+
+// プランを追加します。同じ名前のプランがある場合はNoneを返します。
+pub async fn add_plan(pool: &PgPool, spec: PlanSpec) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            plan (name, cpu, memory, disk, bandwidth, price, status, ip_pool_id)
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        "#,
+        spec.name,
+        spec.cpu,
+        spec.memory,
+        spec.disk,
+        spec.bandwidth,
+        spec.price,
+        spec.status,
+        spec.ip_pool_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+// プランを更新します。存在しない場合はNone、同じ名前のプランがある場合はSome(false)を返します。
+pub async fn set_plan(pool: &PgPool, plan_id: i32, spec: PlanSpec) -> anyhow::Result<Option<bool>> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            plan
+        SET
+            name = $2,
+            cpu = $3,
+            memory = $4,
+            disk = $5,
+            bandwidth = $6,
+            price = $7,
+            status = $8,
+            ip_pool_id = $9
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        plan_id,
+        spec.name,
+        spec.cpu,
+        spec.memory,
+        spec.disk,
+        spec.bandwidth,
+        spec.price,
+        spec.status,
+        spec.ip_pool_id
+    )
+    .fetch_optional(pool)
+    .await;
+    match result {
+        Ok(rec) => Ok(rec.map(|_| true)),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(Some(false)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// プランを削除します。使っているサーバーがある場合はSome(false)を返します。
+pub async fn delete_plan(pool: &PgPool, plan_id: i32) -> anyhow::Result<Option<bool>> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM
+            plan
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        plan_id
+    )
+    .fetch_optional(pool)
+    .await;
+    match result {
+        Ok(rec) => Ok(rec.map(|_| true)),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => Ok(Some(false)),
+        Err(e) => Err(e.into()),
+    }
+}
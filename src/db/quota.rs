@@ -0,0 +1,270 @@
+use sqlx::{PgConnection, PgExecutor, PgPool};
+
+use crate::db::server::SERVER_STATUS_FAILED;
+
+pub const QUOTA_SCOPE_USER: &str = "user";
+pub const QUOTA_SCOPE_ORGANIZATION: &str = "organization";
+
+// 上限は(サーバー数, vCPU, メモリ(MB), ディスク(GB))の順です。
+pub type QuotaLimits = (i32, i32, i32, i32);
+pub type QuotaOverride = (Option<i32>, Option<i32>, Option<i32>, Option<i32>);
+
+pub async fn get_quota_defaults(pool: &PgPool) -> anyhow::Result<Vec<(String, QuotaLimits)>> {
+    let defaults = sqlx::query!(
+        r#"
+        SELECT
+            scope,
+            max_servers,
+            max_cpu,
+            max_memory,
+            max_disk
+        FROM
+            quota_default
+        ORDER BY
+            scope
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.scope,
+            (row.max_servers, row.max_cpu, row.max_memory, row.max_disk),
+        )
+    })
+    .collect();
+    Ok(defaults)
+}
+
+pub async fn set_quota_default(
+    pool: &PgPool,
+    scope: String,
+    (max_servers, max_cpu, max_memory, max_disk): QuotaLimits,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            quota_default
+        SET
+            max_servers = $2,
+            max_cpu = $3,
+            max_memory = $4,
+            max_disk = $5
+        WHERE
+            scope = $1
+        RETURNING
+            scope
+        "#,
+        scope,
+        max_servers,
+        max_cpu,
+        max_memory,
+        max_disk
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+// 既定値に個別の上書きを適用した、ユーザーの上限を取得します。
+pub async fn get_user_quota(pool: &PgPool, user_id: i32) -> anyhow::Result<QuotaLimits> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(quota_override.max_servers, quota_default.max_servers) AS "max_servers!",
+            COALESCE(quota_override.max_cpu, quota_default.max_cpu) AS "max_cpu!",
+            COALESCE(quota_override.max_memory, quota_default.max_memory) AS "max_memory!",
+            COALESCE(quota_override.max_disk, quota_default.max_disk) AS "max_disk!"
+        FROM
+            quota_default
+        LEFT JOIN
+            quota_override ON quota_override.user_id = $2
+        WHERE
+            quota_default.scope = $1
+        "#,
+        QUOTA_SCOPE_USER,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((rec.max_servers, rec.max_cpu, rec.max_memory, rec.max_disk))
+}
+
+pub async fn get_organization_quota(
+    pool: &PgPool,
+    organization_id: i32,
+) -> anyhow::Result<QuotaLimits> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(quota_override.max_servers, quota_default.max_servers) AS "max_servers!",
+            COALESCE(quota_override.max_cpu, quota_default.max_cpu) AS "max_cpu!",
+            COALESCE(quota_override.max_memory, quota_default.max_memory) AS "max_memory!",
+            COALESCE(quota_override.max_disk, quota_default.max_disk) AS "max_disk!"
+        FROM
+            quota_default
+        LEFT JOIN
+            quota_override ON quota_override.organization_id = $2
+        WHERE
+            quota_default.scope = $1
+        "#,
+        QUOTA_SCOPE_ORGANIZATION,
+        organization_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((rec.max_servers, rec.max_cpu, rec.max_memory, rec.max_disk))
+}
+
+pub async fn set_user_quota_override(
+    pool: &PgPool,
+    user_id: i32,
+    (max_servers, max_cpu, max_memory, max_disk): QuotaOverride,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            quota_override (user_id, max_servers, max_cpu, max_memory, max_disk)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id) DO UPDATE SET
+            max_servers = EXCLUDED.max_servers,
+            max_cpu = EXCLUDED.max_cpu,
+            max_memory = EXCLUDED.max_memory,
+            max_disk = EXCLUDED.max_disk
+        "#,
+        user_id,
+        max_servers,
+        max_cpu,
+        max_memory,
+        max_disk
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_organization_quota_override(
+    pool: &PgPool,
+    organization_id: i32,
+    (max_servers, max_cpu, max_memory, max_disk): QuotaOverride,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            quota_override (organization_id, max_servers, max_cpu, max_memory, max_disk)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        ON CONFLICT (organization_id) DO UPDATE SET
+            max_servers = EXCLUDED.max_servers,
+            max_cpu = EXCLUDED.max_cpu,
+            max_memory = EXCLUDED.max_memory,
+            max_disk = EXCLUDED.max_disk
+        "#,
+        organization_id,
+        max_servers,
+        max_cpu,
+        max_memory,
+        max_disk
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_user_quota_override(pool: &PgPool, user_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            quota_override
+        WHERE
+            user_id = $1
+        RETURNING
+            id
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn delete_organization_quota_override(
+    pool: &PgPool,
+    organization_id: i32,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            quota_override
+        WHERE
+            organization_id = $1
+        RETURNING
+            id
+        "#,
+        organization_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+// 所有者ごとにロックを取り、同じ所有者のサーバーの作成を順番に処理します。
+// ロックはトランザクションの終わりまで保持されます。
+pub async fn lock_quota(
+    conn: &mut PgConnection,
+    author_id: i32,
+    organization_id: Option<i32>,
+) -> anyhow::Result<()> {
+    let (scope, id) = match organization_id {
+        Some(organization_id) => (QUOTA_SCOPE_ORGANIZATION, organization_id),
+        None => (QUOTA_SCOPE_USER, author_id),
+    };
+    sqlx::query!(
+        r#"
+        SELECT
+            pg_advisory_xact_lock(hashtext($1), $2)
+        "#,
+        scope,
+        id
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(())
+}
+
+// 組織を指定した場合は組織、指定しない場合はユーザー個人のサーバーの使用量を、上限と同じ順で取得します。
+// 作成に失敗したサーバーは数えません。
+pub async fn get_quota_usage(
+    executor: impl PgExecutor<'_>,
+    author_id: i32,
+    organization_id: Option<i32>,
+) -> anyhow::Result<QuotaLimits> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            count(*)::INTEGER AS "servers!",
+            COALESCE(sum(plan.cpu), 0)::INTEGER AS "cpu!",
+            COALESCE(sum(plan.memory), 0)::INTEGER AS "memory!",
+            COALESCE(sum(plan.disk), 0)::INTEGER AS "disk!"
+        FROM
+            server
+        JOIN
+            plan ON plan.id = server.plan
+        WHERE
+            server.status <> $3
+        AND
+            (
+                ($2::INTEGER IS NULL AND server.organization_id IS NULL AND server.author_id = $1)
+                OR server.organization_id = $2
+            )
+        "#,
+        author_id,
+        organization_id,
+        SERVER_STATUS_FAILED
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok((rec.servers, rec.cpu, rec.memory, rec.disk))
+}
@@ -1,98 +1,214 @@
 use sqlx::PgPool;
 
+use crate::db::{
+    ipam::AllocatedAddresses,
+    quota::{QuotaLimits, get_quota_usage, lock_quota},
+    server_job::JOB_KIND_CREATE,
+};
+
+pub const SERVER_STATUS_PROVISIONING: &str = "provisioning";
+pub const SERVER_STATUS_RUNNING: &str = "running";
+pub const SERVER_STATUS_FAILED: &str = "failed";
+
+// 作成を依頼するサーバーです。
+pub struct NewServer<'a> {
+    pub id: String,
+    pub name: String,
+    pub addresses: &'a AllocatedAddresses,
+    pub plan: i32,
+    pub author_id: i32,
+    pub organization_id: Option<i32>,
+    // 所有者のクォータの上限です。
+    pub limits: QuotaLimits,
+}
+
+// サーバーをprovisioningで登録して割り当てたアドレスを結び付け、作成のジョブを登録します。
+// 登録したジョブのIDを返します。追加するとクォータを超える場合は何もせずNoneを返します。
 pub async fn add_server(
     pool: &PgPool,
-    id: String,
-    name: String,
-    ip_address: String,
-    plan: i32,
-    author_id: i32,
-) -> anyhow::Result<()> {
+    server: NewServer<'_>,
+    payload: String,
+) -> anyhow::Result<Option<i32>> {
+    let mut tx = pool.begin().await?;
+    lock_quota(&mut tx, server.author_id, server.organization_id).await?;
     sqlx::query!(
         r#"
         INSERT INTO
-            server (id, name, ip_address, plan, author_id)
+            server (id, name, ip_address, ipv6_address, ip_pool_id, plan, author_id, organization_id, status)
         VALUES
-            ($1, $2, $3, $4, $5)
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
         "#,
-        id,
-        name,
-        ip_address,
-        plan,
-        author_id
+        server.id,
+        server.name,
+        server.addresses.address,
+        server.addresses.ipv6_address,
+        server.addresses.pool_id,
+        server.plan,
+        server.author_id,
+        server.organization_id,
+        SERVER_STATUS_PROVISIONING
     )
-    .execute(pool)
+    .execute(&mut *tx)
     .await?;
-    Ok(())
-}
-
-pub async fn get_server_ips(pool: &PgPool) -> anyhow::Result<Vec<String>> {
-    let ips = sqlx::query!("SELECT ip_address FROM server")
-        .fetch_all(pool)
-        .await?
-        .into_iter()
-        .map(|row| row.ip_address)
-        .collect();
-    Ok(ips)
+    let (servers, cpu, memory, disk) =
+        get_quota_usage(&mut *tx, server.author_id, server.organization_id).await?;
+    let (max_servers, max_cpu, max_memory, max_disk) = server.limits;
+    if servers > max_servers || cpu > max_cpu || memory > max_memory || disk > max_disk {
+        return Ok(None);
+    }
+    sqlx::query!(
+        r#"
+        UPDATE
+            ip_allocation
+        SET
+            server_id = $1
+        WHERE
+            id = ANY($2)
+        "#,
+        server.id,
+        &server.addresses.allocation_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            server_job (server_id, kind, payload)
+        VALUES
+            ($1, $2, $3)
+        RETURNING id
+        "#,
+        server.id,
+        JOB_KIND_CREATE,
+        payload
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Some(rec.id))
 }
 
 pub async fn get_all_servers_from_user(
     pool: &PgPool,
     user_id: i32,
-) -> anyhow::Result<Vec<(String, String, i32, String)>> {
+) -> anyhow::Result<Vec<(String, String, i32, String, Option<String>, String)>> {
     let servers = sqlx::query!(
         r#"
         SELECT
             id,
             name,
             plan,
-            ip_address
+            ip_address,
+            ipv6_address,
+            status
         FROM
             server
         WHERE
             author_id = $1
+        AND
+            organization_id IS NULL
         "#,
         user_id
     )
     .fetch_all(pool)
     .await?
     .into_iter()
-    .map(|row| (row.id, row.name, row.plan, row.ip_address))
+    .map(|row| {
+        (
+            row.id,
+            row.name,
+            row.plan,
+            row.ip_address,
+            row.ipv6_address,
+            row.status,
+        )
+    })
     .collect();
     Ok(servers)
 }
 
+pub async fn get_servers_by_organization(
+    pool: &PgPool,
+    organization_id: i32,
+) -> anyhow::Result<Vec<(String, String, i32, String, Option<String>, String)>> {
+    let servers = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            plan,
+            ip_address,
+            ipv6_address,
+            status
+        FROM
+            server
+        WHERE
+            organization_id = $1
+        "#,
+        organization_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.name,
+            row.plan,
+            row.ip_address,
+            row.ipv6_address,
+            row.status,
+        )
+    })
+    .collect();
+    Ok(servers)
+}
+
+// 個人のサーバーは作成者のみ、組織のサーバーは指定したロールのメンバーのみが取得できます。
 pub async fn db_get_server_by_id(
     pool: &PgPool,
     server_id: String,
     user_id: i32,
-) -> anyhow::Result<Option<(String, String, i32, String)>> {
+    roles: &[&str],
+) -> anyhow::Result<Option<(String, String, i32, String, Option<String>, String)>> {
     let row = sqlx::query!(
         r#"
         SELECT
             id,
             name,
             plan,
-            ip_address
+            ip_address,
+            ipv6_address,
+            status
         FROM
             server
         WHERE
             id = $1
         AND
-            author_id = $2
+            (
+                (organization_id IS NULL AND author_id = $2)
+                OR EXISTS (
+                    SELECT 1 FROM organization_member
+                    WHERE organization_member.organization_id = server.organization_id
+                    AND organization_member.user_id = $2
+                    AND organization_member.role = ANY($3)
+                )
+            )
         "#,
         server_id,
-        user_id
+        user_id,
+        roles as _
     )
     .fetch_optional(pool)
     .await?;
-    Ok(row.map(|r| (r.id, r.name, r.plan, r.ip_address)))
+    Ok(row.map(|r| (r.id, r.name, r.plan, r.ip_address, r.ipv6_address, r.status)))
 }
 
 pub async fn db_delete_server_by_id(
     pool: &PgPool,
     server_id: String,
     user_id: i32,
+    roles: &[&str],
 ) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
@@ -101,12 +217,103 @@ pub async fn db_delete_server_by_id(
         WHERE
             id = $1
         AND
-            author_id = $2
+            (
+                (organization_id IS NULL AND author_id = $2)
+                OR EXISTS (
+                    SELECT 1 FROM organization_member
+                    WHERE organization_member.organization_id = server.organization_id
+                    AND organization_member.user_id = $2
+                    AND organization_member.role = ANY($3)
+                )
+            )
         "#,
         server_id,
-        user_id
+        user_id,
+        roles as _
     )
     .execute(pool)
     .await?;
     Ok(())
 }
+
+pub async fn get_all_servers(
+    pool: &PgPool,
+) -> anyhow::Result<Vec<(String, String, i32, String, Option<i32>)>> {
+    let servers = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            plan,
+            ip_address,
+            author_id
+        FROM
+            server
+        ORDER BY
+            created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.name, row.plan, row.ip_address, row.author_id))
+    .collect();
+    Ok(servers)
+}
+
+// VMコントローラーでのサーバーのIDを取得します。作成が終わっていない場合はNoneです。
+pub async fn get_domain_id(pool: &PgPool, server_id: &str) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            domain_id
+        FROM
+            server
+        WHERE
+            id = $1
+        "#,
+        server_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.and_then(|r| r.domain_id))
+}
+
+// 作成が終わったサーバーについて、IDとVMコントローラーでのIDの組を取得します。
+pub async fn get_domain_ids(
+    pool: &PgPool,
+    server_ids: &[String],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let domain_ids = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            domain_id AS "domain_id!"
+        FROM
+            server
+        WHERE
+            id = ANY($1)
+        AND
+            domain_id IS NOT NULL
+        "#,
+        server_ids
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.domain_id))
+    .collect();
+    Ok(domain_ids)
+}
+
+pub async fn exist_server(pool: &PgPool, server_id: String) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT count(*) FROM server WHERE id = $1
+        "#,
+        server_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec.count.unwrap_or_default() > 0)
+}
@@ -0,0 +1,238 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+use crate::db::server::{SERVER_STATUS_FAILED, SERVER_STATUS_RUNNING};
+
+pub const JOB_KIND_CREATE: &str = "create";
+
+pub const JOB_STATUS_PENDING: &str = "pending";
+pub const JOB_STATUS_RUNNING: &str = "running";
+pub const JOB_STATUS_SUCCEEDED: &str = "succeeded";
+pub const JOB_STATUS_FAILED: &str = "failed";
+
+// ワーカーが取り出したジョブです。
+pub struct ClaimedServerJob {
+    pub id: i32,
+    pub server_id: String,
+    pub payload: Option<String>,
+    // 今回の実行を含めた実行回数です。
+    pub attempts: i32,
+}
+
+// 実行できるジョブを1つ取り出し、runningにします。
+// 処理中のままlease秒を過ぎたジョブは、ワーカーが止まったとみなして取り出し直します。
+// 複数のワーカーが同じジョブを取り出さないよう、ロック中の行は飛ばします。
+pub async fn claim_server_job(
+    pool: &PgPool,
+    lease: f64,
+) -> anyhow::Result<Option<ClaimedServerJob>> {
+    let rec = sqlx::query_as!(
+        ClaimedServerJob,
+        r#"
+        UPDATE
+            server_job
+        SET
+            status = $1,
+            attempts = attempts + 1,
+            run_at = CURRENT_TIMESTAMP + make_interval(secs => $3),
+            updated_at = CURRENT_TIMESTAMP
+        WHERE
+            id = (
+                SELECT
+                    id
+                FROM
+                    server_job
+                WHERE
+                    status IN ($1, $2)
+                AND
+                    run_at <= CURRENT_TIMESTAMP
+                ORDER BY
+                    run_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+        RETURNING
+            id,
+            server_id,
+            payload,
+            attempts
+        "#,
+        JOB_STATUS_RUNNING,
+        JOB_STATUS_PENDING,
+        lease
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
+
+// ジョブを完了にし、VMコントローラーのIDを記録してサーバーをrunningにします。
+// サーバーが既に削除されている場合はfalseを返します。
+pub async fn complete_server_job(
+    pool: &PgPool,
+    job_id: i32,
+    domain_id: &str,
+) -> anyhow::Result<bool> {
+    finish_server_job(
+        pool,
+        job_id,
+        JOB_STATUS_SUCCEEDED,
+        SERVER_STATUS_RUNNING,
+        Some(domain_id),
+        None,
+    )
+    .await
+}
+
+// 再試行しないことにしたジョブを失敗にし、サーバーをfailedにします。
+// 割り当てたアドレスは、利用者がサーバーを削除するまで保持します。
+pub async fn fail_server_job(pool: &PgPool, job_id: i32, error: &str) -> anyhow::Result<bool> {
+    finish_server_job(
+        pool,
+        job_id,
+        JOB_STATUS_FAILED,
+        SERVER_STATUS_FAILED,
+        None,
+        Some(error),
+    )
+    .await
+}
+
+async fn finish_server_job(
+    pool: &PgPool,
+    job_id: i32,
+    job_status: &str,
+    server_status: &str,
+    domain_id: Option<&str>,
+    error: Option<&str>,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            server_job
+        SET
+            status = $2,
+            payload = NULL,
+            last_error = $3,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE
+            id = $1
+        RETURNING
+            server_id
+        "#,
+        job_id,
+        job_status,
+        error
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(rec) = rec else {
+        return Ok(false);
+    };
+    sqlx::query!(
+        r#"
+        UPDATE
+            server
+        SET
+            status = $2,
+            domain_id = $3,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE
+            id = $1
+        "#,
+        rec.server_id,
+        server_status,
+        domain_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+// 一時的なエラーで失敗したジョブを、delay秒後に再実行します。
+pub async fn retry_server_job(
+    pool: &PgPool,
+    job_id: i32,
+    delay: f64,
+    error: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE
+            server_job
+        SET
+            status = $2,
+            last_error = $3,
+            run_at = CURRENT_TIMESTAMP + make_interval(secs => $4),
+            updated_at = CURRENT_TIMESTAMP
+        WHERE
+            id = $1
+        "#,
+        job_id,
+        JOB_STATUS_PENDING,
+        error,
+        delay
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ServerJob {
+    pub id: i32,
+    pub server_id: String,
+    pub kind: String,
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// サーバーを取得できるユーザーのみ、そのサーバーのジョブを取得できます。
+pub async fn get_server_job(
+    pool: &PgPool,
+    job_id: i32,
+    user_id: i32,
+    roles: &[&str],
+) -> anyhow::Result<Option<ServerJob>> {
+    let rec = sqlx::query_as!(
+        ServerJob,
+        r#"
+        SELECT
+            server_job.id,
+            server_job.server_id,
+            server_job.kind,
+            server_job.status,
+            server_job.attempts,
+            server_job.last_error,
+            server_job.created_at,
+            server_job.updated_at
+        FROM
+            server_job
+        JOIN
+            server ON server.id = server_job.server_id
+        WHERE
+            server_job.id = $1
+        AND
+            (
+                (server.organization_id IS NULL AND server.author_id = $2)
+                OR EXISTS (
+                    SELECT 1 FROM organization_member
+                    WHERE organization_member.organization_id = server.organization_id
+                    AND organization_member.user_id = $2
+                    AND organization_member.role = ANY($3)
+                )
+            )
+        "#,
+        job_id,
+        user_id,
+        roles as _
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec)
+}
@@ -6,32 +6,50 @@ pub async fn db_create_setup_script(
     description: Option<String>,
     script: String,
     author_id: i32,
+    organization_id: Option<i32>,
 ) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
-        INSERT INTO setup_script (title, description, script, author_id)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO setup_script (title, description, script, author_id, organization_id)
+        VALUES ($1, $2, $3, $4, $5)
         "#,
         title,
         description,
         script,
-        author_id
+        author_id,
+        organization_id
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
+// 個人のスクリプトと、所属している組織のスクリプトを取得します。
 pub async fn db_get_all_setup_scripts(
     pool: &PgPool,
-) -> anyhow::Result<Vec<(i32, String, Option<String>, String, i32)>> {
+    user_id: i32,
+) -> anyhow::Result<
+    Vec<(
+        i32,
+        String,
+        Option<String>,
+        String,
+        Option<i32>,
+        Option<i32>,
+    )>,
+> {
     let scripts = sqlx::query!(
         r#"
         SELECT
-            id, title, description, script, author_id
+            id, title, description, script, author_id, organization_id
         FROM
             setup_script
+        WHERE
+            organization_id IS NULL
+        OR
+            organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $1)
         "#,
+        user_id
     )
     .fetch_all(pool)
     .await?
@@ -43,18 +61,29 @@ pub async fn db_get_all_setup_scripts(
             row.description,
             row.script,
             row.author_id,
+            row.organization_id,
         )
     })
     .collect();
     Ok(scripts)
 }
 
-pub async fn get_script_by_id(pool: &PgPool, script_id: i32) -> anyhow::Result<Option<String>> {
+pub async fn get_script_by_id(
+    pool: &PgPool,
+    script_id: i32,
+    user_id: i32,
+) -> anyhow::Result<Option<String>> {
     let script = sqlx::query!(
         r#"
-        SELECT script FROM setup_script WHERE id = $1
+        SELECT script FROM setup_script
+        WHERE id = $1
+        AND (
+            organization_id IS NULL
+            OR organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $2)
+        )
         "#,
-        script_id
+        script_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
@@ -65,23 +94,40 @@ pub async fn get_script_by_id(pool: &PgPool, script_id: i32) -> anyhow::Result<O
 pub async fn get_scriptdata_by_id(
     pool: &PgPool,
     script_id: i32,
-) -> anyhow::Result<Option<(String, Option<String>, String, i32)>> {
+    user_id: i32,
+) -> anyhow::Result<Option<(String, Option<String>, String, Option<i32>, Option<i32>)>> {
     let script = sqlx::query!(
         r#"
-        SELECT title, description, script, author_id FROM setup_script WHERE id = $1
+        SELECT title, description, script, author_id, organization_id FROM setup_script
+        WHERE id = $1
+        AND (
+            organization_id IS NULL
+            OR organization_id IN (SELECT organization_id FROM organization_member WHERE user_id = $2)
+        )
         "#,
-        script_id
+        script_id,
+        user_id
     )
     .fetch_optional(pool)
     .await?;
 
-    Ok(script.map(|row| (row.title, row.description, row.script, row.author_id)))
+    Ok(script.map(|row| {
+        (
+            row.title,
+            row.description,
+            row.script,
+            row.author_id,
+            row.organization_id,
+        )
+    }))
 }
 
+// 個人のスクリプトは作成者のみ、組織のスクリプトは指定したロールのメンバーのみが更新できます。
 pub async fn set_setup_script(
     pool: &PgPool,
     script_id: i32,
     user_id: i32,
+    roles: &[&str],
     title: String,
     description: Option<String>,
     script: String,
@@ -97,13 +143,22 @@ pub async fn set_setup_script(
         WHERE
             id = $4
         AND
-            author_id = $5
+            (
+                (organization_id IS NULL AND author_id = $5)
+                OR EXISTS (
+                    SELECT 1 FROM organization_member
+                    WHERE organization_member.organization_id = setup_script.organization_id
+                    AND organization_member.user_id = $5
+                    AND organization_member.role = ANY($6)
+                )
+            )
         "#,
         title,
         description,
         script,
         script_id,
-        user_id
+        user_id,
+        roles as _
     )
     .execute(pool)
     .await?;
@@ -114,6 +169,7 @@ pub async fn delete_setup_script(
     pool: &PgPool,
     script_id: i32,
     user_id: i32,
+    roles: &[&str],
 ) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
@@ -122,12 +178,39 @@ pub async fn delete_setup_script(
         WHERE
             id = $1
         AND
-            author_id = $2
+            (
+                (organization_id IS NULL AND author_id = $2)
+                OR EXISTS (
+                    SELECT 1 FROM organization_member
+                    WHERE organization_member.organization_id = setup_script.organization_id
+                    AND organization_member.user_id = $2
+                    AND organization_member.role = ANY($3)
+                )
+            )
         "#,
         script_id,
+        user_id,
+        roles as _
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// 個人のスクリプトをすべて削除します。アカウントを削除するときに使います。
+pub async fn delete_personal_setup_scripts(pool: &PgPool, user_id: i32) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            setup_script
+        WHERE
+            author_id = $1
+        AND
+            organization_id IS NULL
+        "#,
         user_id
     )
     .execute(pool)
     .await?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,116 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+// 公開鍵を登録します。同じ鍵が既に登録されている場合はNoneを返します。
+pub async fn add_ssh_key(
+    pool: &PgPool,
+    user_id: i32,
+    name: String,
+    public_key: String,
+    fingerprint: String,
+) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO
+            ssh_key (user_id, name, public_key, fingerprint)
+        VALUES
+            ($1, $2, $3, $4)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        "#,
+        user_id,
+        name,
+        public_key,
+        fingerprint
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.id))
+}
+
+pub async fn get_ssh_keys_by_user(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<Vec<(i32, String, String, String, Option<NaiveDateTime>)>> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            public_key,
+            fingerprint,
+            created_at
+        FROM
+            ssh_key
+        WHERE
+            user_id = $1
+        ORDER BY
+            id
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.name,
+            row.public_key,
+            row.fingerprint,
+            row.created_at,
+        )
+    })
+    .collect();
+    Ok(keys)
+}
+
+// 指定したIDのうち、ユーザーが登録している公開鍵を取得します。
+pub async fn get_public_keys_by_ids(
+    pool: &PgPool,
+    user_id: i32,
+    key_ids: &[i32],
+) -> anyhow::Result<Vec<String>> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT
+            public_key
+        FROM
+            ssh_key
+        WHERE
+            user_id = $1
+        AND
+            id = ANY($2)
+        ORDER BY
+            id
+        "#,
+        user_id,
+        key_ids
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.public_key)
+    .collect();
+    Ok(keys)
+}
+
+pub async fn delete_ssh_key(pool: &PgPool, key_id: i32, user_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            ssh_key
+        WHERE
+            id = $1
+        AND
+            user_id = $2
+        RETURNING
+            id
+        "#,
+        key_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
@@ -1,30 +1,252 @@
+use chrono::NaiveDateTime;
 use sqlx::PgPool;
 
-pub async fn add_token(pool: &PgPool, nonce: String, user_id: i32) -> anyhow::Result<()> {
+pub async fn add_token(
+    pool: &PgPool,
+    nonce: String,
+    user_id: i32,
+    ttl: i64,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+) -> anyhow::Result<()> {
     sqlx::query!(
         r#"
         INSERT INTO
-            session_token(nonce, user_id)
+            session_token(nonce, user_id, expires_at, ip_address, user_agent)
         VALUES
-            ($1, $2)
+            ($1, $2, CURRENT_TIMESTAMP + make_interval(secs => $3), $4, $5)
         "#,
         nonce,
         user_id,
+        ttl as f64,
+        ip_address,
+        user_agent,
     )
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn exist_token(pool: &PgPool, nonce: String, user_id: i32) -> anyhow::Result<bool> {
+// トークンが有効かつユーザーが停止されていなければ、最終利用日時を更新してtrueを返します。
+pub async fn touch_token(
+    pool: &PgPool,
+    nonce: String,
+    user_id: i32,
+    idle_timeout: Option<i64>,
+) -> anyhow::Result<bool> {
     let rec = sqlx::query!(
         r#"
-        SELECT count(*) FROM session_token WHERE nonce = $1 AND user_id = $2
+        UPDATE
+            session_token
+        SET
+            last_used_at = CURRENT_TIMESTAMP
+        FROM
+            users
+        WHERE
+            users.id = session_token.user_id
+        AND
+            users.suspended_at IS NULL
+        AND
+            nonce = $1
+        AND
+            user_id = $2
+        AND
+            expires_at > CURRENT_TIMESTAMP
+        AND
+            ($3::FLOAT8 IS NULL OR last_used_at > CURRENT_TIMESTAMP - make_interval(secs => $3))
+        RETURNING
+            session_token.id
         "#,
         nonce,
+        user_id,
+        idle_timeout.map(|t| t as f64),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+// セッションがmax_age秒以内に発行されたものであればtrueを返します。
+pub async fn is_recent_token(
+    pool: &PgPool,
+    nonce: String,
+    user_id: i32,
+    max_age: i64,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            id
+        FROM
+            session_token
+        WHERE
+            nonce = $1
+        AND
+            user_id = $2
+        AND
+            created_at > CURRENT_TIMESTAMP - make_interval(secs => $3)
+        "#,
+        nonce,
+        user_id,
+        max_age as f64,
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn get_tokens_by_user(
+    pool: &PgPool,
+    user_id: i32,
+    idle_timeout: Option<i64>,
+) -> anyhow::Result<
+    Vec<(
+        i32,
+        String,
+        Option<NaiveDateTime>,
+        NaiveDateTime,
+        Option<String>,
+        Option<String>,
+    )>,
+> {
+    let tokens = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            nonce,
+            created_at,
+            last_used_at,
+            ip_address,
+            user_agent
+        FROM
+            session_token
+        WHERE
+            user_id = $1
+        AND
+            expires_at > CURRENT_TIMESTAMP
+        AND
+            ($2::FLOAT8 IS NULL OR last_used_at > CURRENT_TIMESTAMP - make_interval(secs => $2))
+        ORDER BY
+            last_used_at DESC
+        "#,
+        user_id,
+        idle_timeout.map(|t| t as f64),
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.nonce,
+            row.created_at,
+            row.last_used_at,
+            row.ip_address,
+            row.user_agent,
+        )
+    })
+    .collect();
+    Ok(tokens)
+}
+
+pub async fn delete_token_by_nonce(
+    pool: &PgPool,
+    nonce: String,
+    user_id: i32,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            session_token
+        WHERE
+            nonce = $1
+        AND
+            user_id = $2
+        "#,
+        nonce,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// 削除したセッションのnonceを返します。
+pub async fn delete_token_by_id(
+    pool: &PgPool,
+    token_id: i32,
+    user_id: i32,
+) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            session_token
+        WHERE
+            id = $1
+        AND
+            user_id = $2
+        RETURNING
+            nonce
+        "#,
+        token_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.map(|r| r.nonce))
+}
+
+pub async fn delete_expired_tokens(pool: &PgPool, user_id: i32) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            session_token
+        WHERE
+            user_id = $1
+        AND
+            expires_at <= CURRENT_TIMESTAMP
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_all_tokens_by_user(pool: &PgPool, user_id: i32) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            session_token
+        WHERE
+            user_id = $1
+        "#,
         user_id
     )
-    .fetch_one(pool)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// 指定したnonce以外のセッションをすべて削除します。
+pub async fn delete_other_tokens_by_user(
+    pool: &PgPool,
+    user_id: i32,
+    nonce: String,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            session_token
+        WHERE
+            user_id = $1
+        AND
+            nonce <> $2
+        "#,
+        user_id,
+        nonce
+    )
+    .execute(pool)
     .await?;
-    Ok(rec.count.unwrap() == 1)
+    Ok(())
 }
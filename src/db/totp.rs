@@ -0,0 +1,129 @@
+use sqlx::PgPool;
+
+pub async fn get_totp_secret(pool: &PgPool, user_id: i32) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            totp_secret
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.and_then(|r| r.totp_secret))
+}
+
+// シークレットとリカバリーコードをまとめて登録し、二要素認証を有効にします。
+pub async fn enable_totp(
+    pool: &PgPool,
+    user_id: i32,
+    secret: String,
+    recovery_code_hashes: Vec<String>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            totp_secret = $1
+        WHERE
+            id = $2
+        "#,
+        secret,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            totp_recovery_code
+        WHERE
+            user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            totp_recovery_code (user_id, code_hash)
+        SELECT
+            $1, code_hash
+        FROM
+            UNNEST($2::TEXT[]) AS code_hash
+        "#,
+        user_id,
+        &recovery_code_hashes
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn disable_totp(pool: &PgPool, user_id: i32) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            totp_secret = NULL
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM
+            totp_recovery_code
+        WHERE
+            user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// 未使用のリカバリーコードであれば使用済みにしてtrueを返します。
+pub async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: i32,
+    code_hash: String,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            totp_recovery_code
+        SET
+            used_at = CURRENT_TIMESTAMP
+        WHERE
+            user_id = $1
+        AND
+            code_hash = $2
+        AND
+            used_at IS NULL
+        RETURNING
+            id
+        "#,
+        user_id,
+        code_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
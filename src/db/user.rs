@@ -1,49 +1,88 @@
+use chrono::NaiveDateTime;
 use sqlx::PgPool;
 
+use crate::db::invite_code::consume_invite_code;
+
+// 招待コードを指定した場合は、同じトランザクションで使用回数を増やします。
+// 招待コードが使えなくなっている場合はNoneを返します。
 pub async fn add_user(
     pool: &PgPool,
     username: String,
     email: String,
-    password_hash: String,
-) -> anyhow::Result<i32> {
+    password_hash: Option<String>,
+    invite_code_id: Option<i32>,
+) -> anyhow::Result<Option<i32>> {
+    let mut tx = pool.begin().await?;
+    if let Some(invite_code_id) = invite_code_id
+        && !consume_invite_code(&mut tx, invite_code_id, &email).await?
+    {
+        return Ok(None);
+    }
     let r = sqlx::query!(
         r#"
-        INSERT INTO users (username, email, password_hash)
-        VALUES ($1, $2, $3)
+        INSERT INTO users (username, email, password_hash, invite_code_id)
+        VALUES ($1, $2, $3, $4)
         RETURNING id
         "#,
         username,
         email,
         password_hash,
+        invite_code_id,
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(r.id)
+    tx.commit().await?;
+    Ok(Some(r.id))
 }
 
-pub async fn get_userid_by_email_and_password(
+// ユーザーID、パスワードのハッシュ(未設定の場合はNone)、停止されているかを返します。
+// メールアドレスは大文字と小文字を区別せずに探します。
+pub async fn get_user_credentials_by_email(
     pool: &PgPool,
     email: String,
-    password_hash: String,
-) -> anyhow::Result<Option<i32>> {
+) -> anyhow::Result<Option<(i32, Option<String>, bool)>> {
     let rec = sqlx::query!(
         r#"
         SELECT
-            id
+            id,
+            password_hash,
+            suspended_at IS NOT NULL AS "suspended!"
         FROM
             users
         WHERE
-            email = $1
-        AND
-            password_hash = $2
+            lower(email) = lower($1)
+        ORDER BY
+            id
+        LIMIT 1
         "#,
-        email,
-        password_hash
+        email.trim()
     )
     .fetch_optional(pool)
     .await?;
 
-    Ok(rec.map(|r| r.id))
+    Ok(rec.map(|r| (r.id, r.password_hash, r.suspended)))
+}
+
+pub async fn set_user_password_hash(
+    pool: &PgPool,
+    user_id: i32,
+    password_hash: String,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            password_hash = $1
+        WHERE
+            id = $2
+        "#,
+        password_hash,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
 pub async fn get_userdata_by_id(
@@ -67,3 +106,309 @@ pub async fn get_userdata_by_id(
 
     Ok(rec.map(|r| (r.username, r.email)))
 }
+
+pub async fn get_profile_by_id(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<Option<(String, String, Option<String>, Option<String>, bool)>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            username,
+            email,
+            display_name,
+            avatar_key,
+            use_gravatar
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.map(|r| {
+        (
+            r.username,
+            r.email,
+            r.display_name,
+            r.avatar_key,
+            r.use_gravatar,
+        )
+    }))
+}
+
+// 指定された項目だけを更新します。ユーザー名が既に使われている場合はfalseを返します。
+pub async fn update_profile(
+    pool: &PgPool,
+    user_id: i32,
+    username: Option<String>,
+    display_name: Option<Option<String>>,
+    use_gravatar: Option<bool>,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            username = COALESCE($2, username),
+            display_name = CASE WHEN $3 THEN $4 ELSE display_name END,
+            use_gravatar = COALESCE($5, use_gravatar)
+        WHERE
+            id = $1
+        "#,
+        user_id,
+        username,
+        display_name.is_some(),
+        display_name.flatten(),
+        use_gravatar
+    )
+    .execute(pool)
+    .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// アバターを差し替え、以前のアバターのキーを返します。
+pub async fn set_user_avatar(
+    pool: &PgPool,
+    user_id: i32,
+    avatar_key: Option<String>,
+) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            avatar_key = $2
+        FROM
+            (SELECT avatar_key FROM users WHERE id = $1 FOR UPDATE) AS previous
+        WHERE
+            id = $1
+        RETURNING
+            previous.avatar_key
+        "#,
+        user_id,
+        avatar_key
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.and_then(|r| r.avatar_key))
+}
+
+pub async fn get_userid_by_email(pool: &PgPool, email: String) -> anyhow::Result<Option<i32>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            id
+        FROM
+            users
+        WHERE
+            email = $1
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.map(|r| r.id))
+}
+
+pub async fn set_user_email(pool: &PgPool, user_id: i32, email: String) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            email = $1
+        WHERE
+            id = $2
+        "#,
+        email,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_password_hash_by_id(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            password_hash
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.and_then(|r| r.password_hash))
+}
+
+pub async fn get_user_role(pool: &PgPool, user_id: i32) -> anyhow::Result<Option<String>> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            role
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(rec.map(|r| r.role))
+}
+
+pub async fn get_all_users(
+    pool: &PgPool,
+) -> anyhow::Result<
+    Vec<(
+        i32,
+        String,
+        String,
+        String,
+        Option<NaiveDateTime>,
+        Option<NaiveDateTime>,
+    )>,
+> {
+    let users = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            username,
+            email,
+            role,
+            suspended_at,
+            created_at
+        FROM
+            users
+        ORDER BY
+            id
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        (
+            row.id,
+            row.username,
+            row.email,
+            row.role,
+            row.suspended_at,
+            row.created_at,
+        )
+    })
+    .collect();
+    Ok(users)
+}
+
+pub async fn set_user_role(pool: &PgPool, user_id: i32, role: String) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            role = $1
+        WHERE
+            id = $2
+        RETURNING
+            id
+        "#,
+        role,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn set_user_suspended(
+    pool: &PgPool,
+    user_id: i32,
+    suspended: bool,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            users
+        SET
+            suspended_at = CASE WHEN $1 THEN COALESCE(suspended_at, CURRENT_TIMESTAMP) END
+        WHERE
+            id = $2
+        RETURNING
+            id
+        "#,
+        suspended,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+// ユーザーが停止されているか、存在しない場合はtrueを返します。
+pub async fn is_user_suspended(pool: &PgPool, user_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT
+            suspended_at IS NOT NULL AS "suspended!"
+        FROM
+            users
+        WHERE
+            id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_none_or(|r| r.suspended))
+}
+
+// ユーザーを削除します。関連する行は外部キーによって一緒に削除されます。
+pub async fn delete_user(pool: &PgPool, user_id: i32) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            users
+        WHERE
+            id = $1
+        RETURNING
+            id
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn exist_username(pool: &PgPool, username: String) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        SELECT count(*) FROM users WHERE username = $1
+        "#,
+        username
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(rec.count.unwrap_or_default() > 0)
+}
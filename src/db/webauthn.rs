@@ -0,0 +1,130 @@
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+
+pub async fn add_webauthn_credential(
+    pool: &PgPool,
+    user_id: i32,
+    name: String,
+    credential_id: String,
+    passkey: String,
+) -> anyhow::Result<i32> {
+    let r = sqlx::query!(
+        r#"
+        INSERT INTO
+            webauthn_credential (user_id, name, credential_id, passkey)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        user_id,
+        name,
+        credential_id,
+        passkey
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(r.id)
+}
+
+// ユーザーが登録しているパスキーを、シリアライズされた形式で取得します。
+pub async fn get_passkeys_by_user(pool: &PgPool, user_id: i32) -> anyhow::Result<Vec<String>> {
+    let passkeys = sqlx::query!(
+        r#"
+        SELECT
+            passkey
+        FROM
+            webauthn_credential
+        WHERE
+            user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.passkey)
+    .collect();
+    Ok(passkeys)
+}
+
+// 認証に使われたパスキーの署名カウンターなどを更新します。
+pub async fn touch_webauthn_credential(
+    pool: &PgPool,
+    user_id: i32,
+    credential_id: String,
+    passkey: Option<String>,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        UPDATE
+            webauthn_credential
+        SET
+            passkey = COALESCE($3, passkey),
+            last_used_at = CURRENT_TIMESTAMP
+        WHERE
+            user_id = $1
+        AND
+            credential_id = $2
+        RETURNING
+            id
+        "#,
+        user_id,
+        credential_id,
+        passkey
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
+
+pub async fn get_webauthn_credentials_by_user(
+    pool: &PgPool,
+    user_id: i32,
+) -> anyhow::Result<Vec<(i32, String, Option<NaiveDateTime>, Option<NaiveDateTime>)>> {
+    let credentials = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            last_used_at,
+            created_at
+        FROM
+            webauthn_credential
+        WHERE
+            user_id = $1
+        ORDER BY
+            id
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.name, row.last_used_at, row.created_at))
+    .collect();
+    Ok(credentials)
+}
+
+pub async fn delete_webauthn_credential(
+    pool: &PgPool,
+    credential_id: i32,
+    user_id: i32,
+) -> anyhow::Result<bool> {
+    let rec = sqlx::query!(
+        r#"
+        DELETE FROM
+            webauthn_credential
+        WHERE
+            id = $1
+        AND
+            user_id = $2
+        RETURNING
+            id
+        "#,
+        credential_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rec.is_some())
+}
@@ -1,7 +1,7 @@
 use anyhow::Error;
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
@@ -15,6 +15,8 @@ pub struct ResponseAPIError {
 pub struct APIError {
     pub status: StatusCode,
     pub message: String,
+    // 429の場合に、再試行できるまでの秒数をRetry-Afterヘッダーで返します。
+    pub retry_after: Option<u64>,
 }
 
 impl APIError {
@@ -22,6 +24,15 @@ impl APIError {
         Self {
             status: StatusCode::UNAUTHORIZED,
             message: message.to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Self {
+            status: StatusCode::FORBIDDEN,
+            message: message.to_string(),
+            retry_after: None,
         }
     }
 
@@ -29,6 +40,15 @@ impl APIError {
         Self {
             status: StatusCode::NOT_FOUND,
             message: message.to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn gone(message: &str) -> Self {
+        Self {
+            status: StatusCode::GONE,
+            message: message.to_string(),
+            retry_after: None,
         }
     }
 
@@ -36,6 +56,31 @@ impl APIError {
         Self {
             status: StatusCode::BAD_REQUEST,
             message: message.to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        Self {
+            status: StatusCode::CONFLICT,
+            message: message.to_string(),
+            retry_after: None,
+        }
+    }
+
+    pub fn too_many_requests(message: &str, retry_after: u64) -> Self {
+        Self {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: message.to_string(),
+            retry_after: Some(retry_after),
+        }
+    }
+
+    pub fn service_unavailable(message: &str) -> Self {
+        Self {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: message.to_string(),
+            retry_after: None,
         }
     }
 
@@ -43,6 +88,7 @@ impl APIError {
         Self {
             status: StatusCode::INTERNAL_SERVER_ERROR,
             message: message.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -53,7 +99,15 @@ impl IntoResponse for APIError {
             status: self.status.as_u16(),
             message: self.message,
         });
-        (self.status, response).into_response()
+        match self.retry_after {
+            Some(retry_after) => (
+                self.status,
+                [(RETRY_AFTER, retry_after.to_string())],
+                response,
+            )
+                .into_response(),
+            None => (self.status, response).into_response(),
+        }
     }
 }
 
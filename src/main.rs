@@ -1,24 +1,34 @@
-use std::env;
+use std::{env, net::SocketAddr};
 
 use axum::{
     Router,
-    routing::{delete, get, post, put},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, patch, post, put},
 };
 use http::header::{AUTHORIZATION, CONTENT_TYPE};
 use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
 
 use crate::{
+    db::ipam::ensure_ip_pool,
+    rate_limit::{RateLimitPolicy, RateLimiter, rate_limit},
     routes::{
         server::{create_server, get_all_servers, get_server_plans},
         setup_script::{create_setup_script, get_all_setup_scripts},
         user::{get_user, register_user},
     },
     state::AppState,
+    utils::{
+        avatar::avatar_max_bytes, ipam::default_ip_pool_from_env,
+        provisioning::run_provisioning_worker,
+    },
 };
 
+mod client_info;
 mod db;
 mod error;
+mod rate_limit;
 mod routes;
 mod state;
 mod token;
@@ -30,21 +40,21 @@ async fn main() -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
 
     let state = AppState::connect(&env::var("DATABASE_URL")?, &env::var("REDIS_URL")?).await?;
+    // 環境変数で指定したネットワークを、既定のIPプールとして登録します。
+    ensure_ip_pool(&state.db_pool, default_ip_pool_from_env()?).await?;
+    // サーバーの作成をVMコントローラーに依頼するワーカーです。
+    tokio::spawn(run_provisioning_worker(state.clone()));
 
     let cors = CorsLayer::new()
         .allow_methods(Any)
         .allow_headers([AUTHORIZATION, CONTENT_TYPE])
         .allow_origin(Any);
 
-    let router = Router::new()
-        .route("/", get(|| async { "Hello, World!" }))
-        .route("/users", post(routes::user::create_user))
-        .route("/users/@me", get(get_user))
-        .route("/users/register", post(register_user))
-        .route("/users/login", post(routes::user::issue_user_token))
-        .route("/servers/plans", get(get_server_plans))
+    // VMコントローラーを呼び出すルートには、厳しめの上限を設けます。
+    let controller_routes = Router::new()
+        .route("/users/@me", delete(routes::user::delete_me))
+        .route("/admin/users/{id}", delete(routes::admin::delete_user))
         .route("/servers", post(create_server))
-        .route("/servers/{id}", get(routes::server::get_server_by_id))
         .route("/servers/{id}", delete(routes::server::delete_server))
         .route(
             "/servers/{id}/shutdown",
@@ -58,7 +68,176 @@ async fn main() -> anyhow::Result<()> {
             "/servers/{id}/restart",
             post(routes::server::restart_server),
         )
+        .route(
+            "/admin/servers/{id}/shutdown",
+            post(routes::admin::shutdown_server),
+        )
+        .route(
+            "/admin/servers/{id}/power_on",
+            post(routes::admin::power_on_server),
+        )
+        .route(
+            "/admin/servers/{id}/restart",
+            post(routes::admin::restart_server),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            RateLimiter::new(&state, RateLimitPolicy::from_env("controller", 10, 60)),
+            rate_limit,
+        ));
+
+    let router = Router::new()
+        .route("/", get(|| async { "Hello, World!" }))
+        .route("/users", post(routes::user::create_user))
+        .route("/users/@me", get(get_user))
+        .route("/users/@me", patch(routes::user::update_user))
+        .route(
+            "/users/@me/avatar",
+            put(routes::avatar::upload_avatar).layer(DefaultBodyLimit::max(avatar_max_bytes())),
+        )
+        .route("/users/@me/avatar", delete(routes::avatar::delete_avatar))
+        .route("/avatars/{key}", get(routes::avatar::get_avatar))
+        .route("/users/@me/logout", post(routes::user::logout))
+        .route(
+            "/users/@me/2fa/totp",
+            post(routes::totp::start_totp_enrollment),
+        )
+        .route(
+            "/users/@me/2fa/totp",
+            delete(routes::totp::disable_totp_enrollment),
+        )
+        .route(
+            "/users/@me/2fa/totp/confirm",
+            post(routes::totp::confirm_totp_enrollment),
+        )
+        .route(
+            "/users/@me/2fa/recovery-codes",
+            post(routes::totp::regenerate_recovery_codes),
+        )
+        .route("/users/@me/password", put(routes::user::change_password))
+        .route("/users/@me/email", put(routes::user::change_email))
+        .route(
+            "/users/@me/email/confirm",
+            post(routes::user::confirm_email_change),
+        )
+        .route("/users/@me/sessions", get(routes::user::get_sessions))
+        .route(
+            "/users/@me/audit-log",
+            get(routes::audit_log::get_my_audit_log),
+        )
+        .route(
+            "/users/@me/invites",
+            post(routes::invite_code::create_invite_code),
+        )
+        .route(
+            "/users/@me/invites",
+            get(routes::invite_code::get_my_invite_codes),
+        )
+        .route(
+            "/users/@me/invites/{id}",
+            delete(routes::invite_code::revoke_my_invite_code),
+        )
+        .route(
+            "/users/@me/webauthn/register",
+            post(routes::webauthn::start_webauthn_registration),
+        )
+        .route(
+            "/users/@me/webauthn/register/confirm",
+            post(routes::webauthn::finish_webauthn_registration),
+        )
+        .route(
+            "/users/@me/webauthn/credentials",
+            get(routes::webauthn::get_webauthn_credentials),
+        )
+        .route(
+            "/users/@me/webauthn/credentials/{id}",
+            delete(routes::webauthn::revoke_webauthn_credential),
+        )
+        .route("/users/@me/ssh-keys", post(routes::ssh_key::create_ssh_key))
+        .route("/users/@me/ssh-keys", get(routes::ssh_key::get_ssh_keys))
+        .route(
+            "/users/@me/ssh-keys/{id}",
+            delete(routes::ssh_key::remove_ssh_key),
+        )
+        .route("/users/@me/api-keys", post(routes::api_key::create_api_key))
+        .route("/users/@me/api-keys", get(routes::api_key::get_api_keys))
+        .route(
+            "/users/@me/api-keys/{id}",
+            delete(routes::api_key::revoke_api_key),
+        )
+        .route(
+            "/users/@me/sessions/{id}",
+            delete(routes::user::revoke_session),
+        )
+        .route("/users/register", post(register_user))
+        .route("/users/login", post(routes::user::issue_user_token))
+        .route(
+            "/users/login/totp",
+            post(routes::totp::verify_login_challenge),
+        )
+        .route(
+            "/users/password-reset",
+            post(routes::user::request_password_reset),
+        )
+        .route(
+            "/users/password-reset/confirm",
+            post(routes::user::confirm_password_reset),
+        )
+        .route(
+            "/users/login/webauthn",
+            post(routes::webauthn::start_webauthn_login),
+        )
+        .route(
+            "/users/login/webauthn/confirm",
+            post(routes::webauthn::finish_webauthn_login),
+        )
+        .route("/auth/oidc/authorize", get(routes::oidc::oidc_authorize))
+        .route("/auth/oidc/callback", post(routes::oidc::oidc_callback))
+        .route(
+            "/organizations",
+            post(routes::organization::create_organization),
+        )
+        .route(
+            "/organizations",
+            get(routes::organization::get_my_organizations),
+        )
+        .route(
+            "/organizations/invites/accept",
+            post(routes::organization::accept_invite),
+        )
+        .route(
+            "/organizations/{id}",
+            get(routes::organization::get_organization_detail),
+        )
+        .route(
+            "/organizations/{id}",
+            delete(routes::organization::remove_organization),
+        )
+        .route(
+            "/organizations/{id}/servers",
+            get(routes::organization::get_organization_servers),
+        )
+        .route(
+            "/organizations/{id}/quota",
+            get(routes::quota::get_organization_quota_usage),
+        )
+        .route(
+            "/organizations/{id}/invites",
+            post(routes::organization::invite_member),
+        )
+        .route(
+            "/organizations/{id}/members/{user_id}",
+            put(routes::organization::put_member_role),
+        )
+        .route(
+            "/organizations/{id}/members/{user_id}",
+            delete(routes::organization::remove_member),
+        )
+        .route("/servers/plans", get(get_server_plans))
+        .route("/servers/regions", get(routes::server::get_server_regions))
+        .route("/servers/{id}", get(routes::server::get_server_by_id))
+        .route("/jobs/{id}", get(routes::job::get_job))
         .route("/users/@me/servers", get(get_all_servers))
+        .route("/users/@me/quota", get(routes::quota::get_my_quota))
         .route("/setup-scripts", post(create_setup_script))
         .route("/setup-scripts", get(get_all_setup_scripts))
         .route(
@@ -73,10 +252,80 @@ async fn main() -> anyhow::Result<()> {
             "/setup-scripts/{id}",
             delete(routes::setup_script::delete_script),
         )
+        .route("/admin/users", get(routes::admin::get_users))
+        .route("/admin/users/{id}/role", put(routes::admin::put_user_role))
+        .route(
+            "/admin/users/{id}/suspend",
+            post(routes::admin::suspend_user),
+        )
+        .route(
+            "/admin/users/{id}/unsuspend",
+            post(routes::admin::unsuspend_user),
+        )
+        .route(
+            "/admin/invites",
+            get(routes::invite_code::get_all_invite_codes),
+        )
+        .route(
+            "/admin/invites/{id}",
+            delete(routes::invite_code::revoke_any_invite_code),
+        )
+        .route("/admin/servers", get(routes::admin::get_servers))
+        .route("/admin/audit-log", get(routes::audit_log::get_audit_log))
+        .route("/admin/ip-pools", get(routes::ipam::get_ip_pool_usage))
+        .route("/admin/ip-pools", post(routes::ipam::create_ip_pool))
+        .route("/admin/ip-pools/{id}", put(routes::ipam::put_ip_pool))
+        .route("/admin/ip-pools/{id}", delete(routes::ipam::remove_ip_pool))
+        .route(
+            "/admin/ip-pools/{id}/reservations",
+            get(routes::ipam::get_reservations),
+        )
+        .route(
+            "/admin/ip-pools/{id}/reservations",
+            post(routes::ipam::create_reservation),
+        )
+        .route(
+            "/admin/ip-pools/{id}/reservations/{reservation_id}",
+            delete(routes::ipam::remove_reservation),
+        )
+        .route("/admin/plans", get(routes::plan::get_all_plans))
+        .route("/admin/plans", post(routes::plan::create_plan))
+        .route("/admin/plans/{id}", put(routes::plan::put_plan))
+        .route("/admin/plans/{id}", delete(routes::plan::remove_plan))
+        .route("/admin/quotas", get(routes::quota::get_default_quotas))
+        .route(
+            "/admin/quotas/{scope}",
+            put(routes::quota::put_default_quota),
+        )
+        .route(
+            "/admin/users/{id}/quota",
+            put(routes::quota::put_user_quota),
+        )
+        .route(
+            "/admin/users/{id}/quota",
+            delete(routes::quota::delete_user_quota),
+        )
+        .route(
+            "/admin/organizations/{id}/quota",
+            put(routes::quota::put_organization_quota),
+        )
+        .route(
+            "/admin/organizations/{id}/quota",
+            delete(routes::quota::delete_organization_quota),
+        )
+        .merge(controller_routes)
+        .layer(middleware::from_fn_with_state(
+            RateLimiter::new(&state, RateLimitPolicy::from_env("default", 300, 60)),
+            rate_limit,
+        ))
         .layer(cors)
         .with_state(state);
 
     let listener = TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, router).await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
@@ -0,0 +1,205 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bb8_redis::{RedisConnectionManager, bb8, redis};
+use http::{HeaderValue, header::AUTHORIZATION};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::{client_info::ClientInfo, error::APIError, state::AppState};
+
+// インメモリの記録がこの件数を超えたら、古いものを掃除します。
+const FALLBACK_MAX_KEYS: usize = 10000;
+const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// ルートのグループごとの上限です。window秒の間にlimit回までリクエストできます。
+#[derive(Clone, Copy)]
+pub struct RateLimitPolicy {
+    pub name: &'static str,
+    pub limit: u64,
+    pub window: u64,
+}
+
+impl RateLimitPolicy {
+    // RATE_LIMIT_{NAME}_LIMITとRATE_LIMIT_{NAME}_WINDOWで上書きできます。
+    pub fn from_env(name: &'static str, limit: u64, window: u64) -> Self {
+        let var = |suffix: &str| {
+            env::var(format!("RATE_LIMIT_{}_{suffix}", name.to_uppercase()))
+                .ok()
+                .and_then(|v| v.parse().ok())
+        };
+        Self {
+            name,
+            limit: var("LIMIT").unwrap_or(limit),
+            window: var("WINDOW").unwrap_or(window),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    redis_pool: Arc<bb8::Pool<RedisConnectionManager>>,
+    // Redisに接続できないときに使う、プロセス内の記録です。
+    fallback: Arc<Mutex<HashMap<String, VecDeque<u64>>>>,
+}
+
+struct WindowState {
+    count: u64,
+    // 一番古いリクエストが窓から外れるまでの秒数です。
+    reset: u64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl RateLimiter {
+    pub fn new(state: &AppState, policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            redis_pool: state.redis_pool.clone(),
+            fallback: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn window_millis(&self) -> u64 {
+        self.policy.window * 1000
+    }
+
+    fn reset_after(&self, oldest: u64, now: u64) -> u64 {
+        (oldest + self.window_millis())
+            .saturating_sub(now)
+            .div_ceil(1000)
+    }
+
+    // スライディングウィンドウでリクエストを記録し、窓の中のリクエスト数を返します。
+    // 上限を超えたリクエストは記録に残しません。
+    async fn hit_redis(&self, key: &str, now: u64) -> anyhow::Result<WindowState> {
+        // Redisが落ちているときに、接続の待ち時間でリクエストが詰まらないようにします。
+        let mut conn = tokio::time::timeout(REDIS_TIMEOUT, self.redis_pool.get()).await??;
+        let member = format!("{now}-{}", rand::rng().random::<u32>());
+        let (count, oldest): (u64, Vec<(String, f64)>) = redis::pipe()
+            .atomic()
+            .zrembyscore(key, 0, now.saturating_sub(self.window_millis()))
+            .ignore()
+            .zadd(key, &member, now)
+            .ignore()
+            .pexpire(key, self.window_millis() as i64)
+            .ignore()
+            .zcard(key)
+            .zrange_withscores(key, 0, 0)
+            .query_async(&mut *conn)
+            .await?;
+        if count > self.policy.limit {
+            let _: () = redis::cmd("ZREM")
+                .arg(key)
+                .arg(&member)
+                .query_async(&mut *conn)
+                .await?;
+        }
+        let oldest = oldest.first().map_or(now, |(_, score)| *score as u64);
+        Ok(WindowState {
+            count,
+            reset: self.reset_after(oldest, now),
+        })
+    }
+
+    fn hit_memory(&self, key: &str, now: u64) -> WindowState {
+        let mut fallback = self.fallback.lock().unwrap_or_else(|e| e.into_inner());
+        let window_start = now.saturating_sub(self.window_millis());
+        if fallback.len() > FALLBACK_MAX_KEYS {
+            fallback.retain(|_, hits| hits.back().is_some_and(|&hit| hit > window_start));
+        }
+        let hits = fallback.entry(key.to_string()).or_default();
+        while hits.front().is_some_and(|&hit| hit <= window_start) {
+            hits.pop_front();
+        }
+        let count = hits.len() as u64 + 1;
+        if count <= self.policy.limit {
+            hits.push_back(now);
+        }
+        WindowState {
+            count,
+            reset: self.reset_after(hits.front().copied().unwrap_or(now), now),
+        }
+    }
+
+    async fn hit(&self, subject: &str) -> WindowState {
+        let key = format!("rate_limit:{}:{subject}", self.policy.name);
+        let now = now_millis();
+        match self.hit_redis(&key, now).await {
+            Ok(window) => window,
+            Err(e) => {
+                tracing::warn!("Falling back to in-memory rate limiting: {}", e);
+                self.hit_memory(&key, now)
+            }
+        }
+    }
+}
+
+// トークンごととIPアドレスごとに、それぞれ上限を適用します。
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Result<Response, APIError> {
+    let mut subjects = Vec::new();
+    if let Some(authorization) = request.headers().get(AUTHORIZATION) {
+        // トークンそのものは保存しないよう、ハッシュをキーにします。
+        subjects.push(format!(
+            "token:{:x}",
+            Sha256::digest(authorization.as_bytes())
+        ));
+    }
+    if let Some(ip_address) = &client.ip_address {
+        subjects.push(format!("ip:{ip_address}"));
+    }
+
+    let policy = limiter.policy;
+    let mut remaining = policy.limit;
+    let mut reset = 0;
+    let mut exceeded = false;
+    for subject in &subjects {
+        let window = limiter.hit(subject).await;
+        remaining = remaining.min(policy.limit.saturating_sub(window.count));
+        reset = reset.max(window.reset);
+        exceeded |= window.count > policy.limit;
+    }
+
+    let mut response = if exceeded {
+        APIError::too_many_requests("Rate limit exceeded", reset).into_response()
+    } else {
+        next.run(request).await
+    };
+    // 複数のグループが重なる場合は、残りが少ない方のヘッダーを残します。
+    let headers = response.headers_mut();
+    if headers
+        .get("ratelimit-remaining")
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+        .is_some_and(|inner| inner <= remaining)
+    {
+        return Ok(response);
+    }
+    headers.insert("ratelimit-limit", HeaderValue::from(policy.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(reset));
+    headers.insert(
+        "ratelimit-policy",
+        HeaderValue::from_str(&format!("{};w={}", policy.limit, policy.window))?,
+    );
+    Ok(response)
+}
@@ -0,0 +1,236 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client_info::ClientInfo,
+    db::{
+        server::{exist_server, get_all_servers},
+        user::{get_all_users, set_user_role, set_user_suspended},
+    },
+    error::{APIError, APIResult},
+    routes::{server::require_domain_id, user::delete_account},
+    state::AppState,
+    token::{AdminToken, ROLE_ADMIN, ROLE_USER},
+    utils::{
+        api::domain,
+        audit::{
+            AUDIT_SERVER_POWER_ON, AUDIT_SERVER_RESTART, AUDIT_SERVER_SHUTDOWN, audit_result,
+            server_target,
+        },
+        token_cache::invalidate_user_tokens,
+    },
+};
+
+#[derive(Serialize)]
+pub struct AdminUserResponse {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub role: String,
+    pub suspended_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// すべてのユーザーの一覧を取得します。
+pub async fn get_users(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<AdminUserResponse>>> {
+    let users = get_all_users(&state.db_pool)
+        .await?
+        .into_iter()
+        .map(
+            |(id, username, email, role, suspended_at, created_at)| AdminUserResponse {
+                id,
+                username,
+                email,
+                role,
+                suspended_at,
+                created_at,
+            },
+        )
+        .collect();
+    Ok(Json(users))
+}
+
+#[derive(Deserialize)]
+pub struct SetUserRoleRequest {
+    pub role: String,
+}
+
+pub async fn put_user_role(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+    Json(payload): Json<SetUserRoleRequest>,
+) -> APIResult<()> {
+    if payload.role != ROLE_USER && payload.role != ROLE_ADMIN {
+        return Err(APIError::bad_request("Unknown role"));
+    }
+    if user_id == token.user_id {
+        return Err(APIError::bad_request("You cannot change your own role"));
+    }
+    if !set_user_role(&state.db_pool, user_id, payload.role).await? {
+        return Err(APIError::not_found("User not found"));
+    }
+    Ok(())
+}
+
+// ユーザーのサーバーとアカウントを削除します。
+pub async fn delete_user(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if user_id == token.user_id {
+        return Err(APIError::bad_request(
+            "You cannot delete your own account here",
+        ));
+    }
+    delete_account(&state, user_id).await
+}
+
+// アカウントを停止します。停止中はすべてのトークンが使えなくなります。
+pub async fn suspend_user(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if user_id == token.user_id {
+        return Err(APIError::bad_request("You cannot suspend yourself"));
+    }
+    if !set_user_suspended(&state.db_pool, user_id, true).await? {
+        return Err(APIError::not_found("User not found"));
+    }
+    invalidate_user_tokens(&state.redis_pool, user_id).await?;
+    Ok(())
+}
+
+pub async fn unsuspend_user(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if !set_user_suspended(&state.db_pool, user_id, false).await? {
+        return Err(APIError::not_found("User not found"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct AdminServerResponse {
+    pub id: String,
+    pub name: String,
+    pub plan: i32,
+    pub ip_address: String,
+    pub author_id: Option<i32>,
+}
+
+// すべてのユーザーのサーバーの一覧を取得します。
+pub async fn get_servers(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<AdminServerResponse>>> {
+    let servers = get_all_servers(&state.db_pool)
+        .await?
+        .into_iter()
+        .map(
+            |(id, name, plan, ip_address, author_id)| AdminServerResponse {
+                id,
+                name,
+                plan,
+                ip_address,
+                author_id,
+            },
+        )
+        .collect();
+    Ok(Json(servers))
+}
+
+pub async fn shutdown_server(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    client: ClientInfo,
+    Path((server_id,)): Path<(String,)>,
+) -> APIResult<()> {
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        if !exist_server(&state.db_pool, server_id.clone()).await? {
+            return Err(APIError::not_found("Server not found"));
+        }
+
+        domain::shutdown_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_SHUTDOWN,
+        target,
+        &result,
+    )
+    .await;
+    result
+}
+
+pub async fn power_on_server(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    client: ClientInfo,
+    Path((server_id,)): Path<(String,)>,
+) -> APIResult<()> {
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        if !exist_server(&state.db_pool, server_id.clone()).await? {
+            return Err(APIError::not_found("Server not found"));
+        }
+
+        domain::power_on_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_POWER_ON,
+        target,
+        &result,
+    )
+    .await;
+    result
+}
+
+pub async fn restart_server(
+    State(state): State<AppState>,
+    AdminToken(token): AdminToken,
+    client: ClientInfo,
+    Path((server_id,)): Path<(String,)>,
+) -> APIResult<()> {
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        if !exist_server(&state.db_pool, server_id.clone()).await? {
+            return Err(APIError::not_found("Server not found"));
+        }
+
+        domain::restart_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_RESTART,
+        target,
+        &result,
+    )
+    .await;
+    result
+}
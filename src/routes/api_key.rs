@@ -0,0 +1,118 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client_info::ClientInfo,
+    db::api_key::{add_api_key, delete_api_key, get_api_keys_by_user},
+    error::{APIError, APIResult},
+    state::AppState,
+    token::{ALL_SCOPES, API_KEY_PREFIX, Token},
+    utils::audit::{AUDIT_API_KEY_CREATE, record_audit_log},
+};
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct CreateApiKeyResponse {
+    pub id: i32,
+    // APIキーはこのレスポンスでしか確認できません。
+    pub key: String,
+}
+
+// APIキーを発行します。
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    token: Token,
+    client: ClientInfo,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> APIResult<Json<CreateApiKeyResponse>> {
+    token.require_session()?;
+    if payload.scopes.is_empty() {
+        return Err(APIError::bad_request("At least one scope is required"));
+    }
+    if let Some(scope) = payload
+        .scopes
+        .iter()
+        .find(|scope| !ALL_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(APIError::bad_request(&format!("Unknown scope: {scope}")));
+    }
+    let api_key = Token::new(token.user_id)?;
+    let id = add_api_key(
+        &state.db_pool,
+        token.user_id,
+        payload.name,
+        api_key.get_nonce_hash(),
+        payload.scopes,
+        payload.expires_at,
+    )
+    .await?
+    .ok_or_else(|| APIError::bad_request("expires_at must be in the future"))?;
+    record_audit_log(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_API_KEY_CREATE,
+        Some(format!("api_key:{id}")),
+        None,
+    )
+    .await;
+    Ok(Json(CreateApiKeyResponse {
+        id,
+        key: format!("{API_KEY_PREFIX}{}", api_key.generate()?),
+    }))
+}
+
+#[derive(Serialize)]
+pub struct GetApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// 発行済みのAPIキーの一覧を取得します。
+pub async fn get_api_keys(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<GetApiKeyResponse>>> {
+    token.require_session()?;
+    let keys = get_api_keys_by_user(&state.db_pool, token.user_id)
+        .await?
+        .into_iter()
+        .map(
+            |(id, name, scopes, expires_at, last_used_at, created_at)| GetApiKeyResponse {
+                id,
+                name,
+                scopes,
+                expires_at,
+                last_used_at,
+                created_at,
+            },
+        )
+        .collect();
+    Ok(Json(keys))
+}
+
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    token: Token,
+    Path((key_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if !delete_api_key(&state.db_pool, key_id, token.user_id).await? {
+        return Err(APIError::not_found("API key not found"));
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::audit_log::{AuditLog, AuditLogFilter, get_audit_logs_by_user, search_audit_logs},
+    error::APIResult,
+    state::AppState,
+    token::{AdminToken, Token},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct AuditLogPageQuery {
+    // 前のページのnext_beforeを指定すると、続きを取得します。
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl AuditLogPageQuery {
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Serialize)]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditLog>,
+    // 続きがある可能性がある場合のみ返します。
+    pub next_before: Option<i64>,
+}
+
+impl AuditLogResponse {
+    fn new(entries: Vec<AuditLog>, limit: i64) -> Self {
+        let next_before = if entries.len() as i64 == limit {
+            entries.last().map(|entry| entry.id)
+        } else {
+            None
+        };
+        Self {
+            entries,
+            next_before,
+        }
+    }
+}
+
+// 自分の操作と、自分のアカウントに対する操作の記録を取得します。
+pub async fn get_my_audit_log(
+    State(state): State<AppState>,
+    token: Token,
+    Query(page): Query<AuditLogPageQuery>,
+) -> APIResult<Json<AuditLogResponse>> {
+    token.require_session()?;
+    let limit = page.limit();
+    let entries = get_audit_logs_by_user(&state.db_pool, token.user_id, page.before, limit).await?;
+    Ok(Json(AuditLogResponse::new(entries, limit)))
+}
+
+// すべての記録を条件で絞り込んで取得します。
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Query(page): Query<AuditLogPageQuery>,
+    Query(filter): Query<AuditLogFilter>,
+) -> APIResult<Json<AuditLogResponse>> {
+    let limit = page.limit();
+    let entries = search_audit_logs(&state.db_pool, &filter, page.before, limit).await?;
+    Ok(Json(AuditLogResponse::new(entries, limit)))
+}
@@ -0,0 +1,82 @@
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use serde::Serialize;
+
+use crate::{
+    db::user::{get_profile_by_id, set_user_avatar},
+    error::{APIError, APIResult},
+    state::AppState,
+    token::Token,
+    utils::{
+        avatar::{avatar_max_bytes, avatar_url, is_valid_avatar_key, process_avatar},
+        passcode::generate_secret_token,
+    },
+};
+
+#[derive(Serialize)]
+pub struct UploadAvatarResponse {
+    pub avatar_url: Option<String>,
+}
+
+// アバターをアップロードします。リクエストの本文は画像そのものです。
+pub async fn upload_avatar(
+    State(state): State<AppState>,
+    token: Token,
+    body: Bytes,
+) -> APIResult<Json<UploadAvatarResponse>> {
+    token.require_session()?;
+    if body.len() > avatar_max_bytes() {
+        return Err(APIError::bad_request("Avatar is too large"));
+    }
+    let avatar = process_avatar(body.to_vec())
+        .await?
+        .ok_or_else(|| APIError::bad_request("Unsupported image"))?;
+    // アップロードのたびにキーを変え、古いアバターがキャッシュされ続けないようにします。
+    let avatar_key = format!("{}.png", generate_secret_token()?);
+    state.avatar_storage.put(&avatar_key, avatar).await?;
+    if let Some(previous) =
+        set_user_avatar(&state.db_pool, token.user_id, Some(avatar_key.clone())).await?
+    {
+        state.avatar_storage.delete(&previous).await?;
+    }
+    let (_, email, _, _, use_gravatar) = get_profile_by_id(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("User not found"))?;
+    Ok(Json(UploadAvatarResponse {
+        avatar_url: avatar_url(Some(&avatar_key), use_gravatar, &email),
+    }))
+}
+
+pub async fn delete_avatar(State(state): State<AppState>, token: Token) -> APIResult<()> {
+    token.require_session()?;
+    if let Some(previous) = set_user_avatar(&state.db_pool, token.user_id, None).await? {
+        state.avatar_storage.delete(&previous).await?;
+    }
+    Ok(())
+}
+
+pub async fn get_avatar(
+    State(state): State<AppState>,
+    Path((avatar_key,)): Path<(String,)>,
+) -> APIResult<impl IntoResponse> {
+    if !is_valid_avatar_key(&avatar_key) {
+        return Err(APIError::not_found("Avatar not found"));
+    }
+    let avatar = state
+        .avatar_storage
+        .get(&avatar_key)
+        .await?
+        .ok_or_else(|| APIError::not_found("Avatar not found"))?;
+    Ok((
+        [
+            (CONTENT_TYPE, "image/png"),
+            (CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        avatar,
+    ))
+}
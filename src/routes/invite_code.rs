@@ -0,0 +1,177 @@
+use std::env;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        invite_code::{add_invite_code, get_invite_codes, revoke_invite_code},
+        user::get_user_role,
+    },
+    error::{APIError, APIResult},
+    state::AppState,
+    token::{AdminToken, ROLE_ADMIN, Token},
+    utils::passcode::generate_secret_token,
+};
+
+const DEFAULT_USER_INVITE_MAX_USES: i32 = 1;
+
+// 管理者以外が作成できる招待コードの使用回数の上限です。
+fn user_invite_max_uses() -> i32 {
+    env::var("USER_INVITE_MAX_USES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_USER_INVITE_MAX_USES)
+}
+
+const DEFAULT_USER_INVITE_MAX_CODES: i64 = 5;
+
+// 管理者以外が30日間に作成できる招待コードの数の上限です。
+fn user_invite_max_codes() -> i64 {
+    env::var("USER_INVITE_MAX_CODES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_USER_INVITE_MAX_CODES)
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteCodeRequest {
+    pub max_uses: Option<i32>,
+    pub email: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct GetInviteCodeResponse {
+    pub id: i32,
+    pub code: String,
+    pub created_by: Option<i32>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub email: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// 招待コードを作成します。
+pub async fn create_invite_code(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<CreateInviteCodeRequest>,
+) -> APIResult<Json<GetInviteCodeResponse>> {
+    token.require_session()?;
+    let max_uses = payload.max_uses.unwrap_or(1);
+    if max_uses < 1 {
+        return Err(APIError::bad_request("max_uses must be at least 1"));
+    }
+    let is_admin = get_user_role(&state.db_pool, token.user_id)
+        .await?
+        .as_deref()
+        == Some(ROLE_ADMIN);
+    if !is_admin && max_uses > user_invite_max_uses() {
+        return Err(APIError::bad_request(&format!(
+            "max_uses must be at most {}",
+            user_invite_max_uses()
+        )));
+    }
+    if payload
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= chrono::Utc::now().naive_utc())
+    {
+        return Err(APIError::bad_request("expires_at must be in the future"));
+    }
+    let code = generate_secret_token()?;
+    let id = add_invite_code(
+        &state.db_pool,
+        code.clone(),
+        token.user_id,
+        max_uses,
+        payload.email.clone(),
+        payload.expires_at,
+        (!is_admin).then(user_invite_max_codes),
+    )
+    .await?
+    .ok_or_else(|| APIError::forbidden("Too many invite codes created recently"))?;
+    Ok(Json(GetInviteCodeResponse {
+        id,
+        code,
+        created_by: Some(token.user_id),
+        max_uses,
+        uses: 0,
+        email: payload.email,
+        expires_at: payload.expires_at,
+        revoked_at: None,
+        created_at: None,
+    }))
+}
+
+async fn list_invite_codes(
+    state: &AppState,
+    created_by: Option<i32>,
+) -> APIResult<Json<Vec<GetInviteCodeResponse>>> {
+    let codes = get_invite_codes(&state.db_pool, created_by)
+        .await?
+        .into_iter()
+        .map(
+            |(id, code, created_by, max_uses, uses, email, expires_at, revoked_at, created_at)| {
+                GetInviteCodeResponse {
+                    id,
+                    code,
+                    created_by,
+                    max_uses,
+                    uses,
+                    email,
+                    expires_at,
+                    revoked_at,
+                    created_at,
+                }
+            },
+        )
+        .collect();
+    Ok(Json(codes))
+}
+
+// 自分が作成した招待コードの一覧を取得します。
+pub async fn get_my_invite_codes(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<GetInviteCodeResponse>>> {
+    token.require_session()?;
+    list_invite_codes(&state, Some(token.user_id)).await
+}
+
+pub async fn revoke_my_invite_code(
+    State(state): State<AppState>,
+    token: Token,
+    Path((invite_code_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if !revoke_invite_code(&state.db_pool, invite_code_id, Some(token.user_id)).await? {
+        return Err(APIError::not_found("Invite code not found"));
+    }
+    Ok(())
+}
+
+// すべての招待コードの一覧を取得します。
+pub async fn get_all_invite_codes(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<GetInviteCodeResponse>>> {
+    list_invite_codes(&state, None).await
+}
+
+pub async fn revoke_any_invite_code(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((invite_code_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if !revoke_invite_code(&state.db_pool, invite_code_id, None).await? {
+        return Err(APIError::not_found("Invite code not found"));
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::ipam::{
+        IpPoolDeletion, IpPoolSpec, add_ip_pool, add_ip_reservation, delete_ip_pool,
+        delete_ip_reservation, get_ip_pool, get_ip_pools, get_ip_reservations, set_ip_pool,
+    },
+    error::{APIError, APIResult},
+    state::AppState,
+    token::AdminToken,
+    utils::{
+        ip_calc::IpNet,
+        ipam::{IpPoolNetwork, IpReservationRange, host_address, parse_dns_servers},
+    },
+};
+
+#[derive(Serialize)]
+pub struct IpNetworkUsageResponse {
+    pub cidr: String,
+    pub gateway: String,
+    // ゲートウェイを除いた、サーバーに割り当てられるアドレスの数です。
+    pub total: u128,
+    pub allocated: u128,
+    // 予約範囲に含まれ、割り当てられていないアドレスの数です。
+    pub reserved: u128,
+    pub available: u128,
+}
+
+#[derive(Serialize)]
+pub struct IpPoolResponse {
+    pub id: i32,
+    pub name: String,
+    pub ipv4: IpNetworkUsageResponse,
+    pub ipv6: Option<IpNetworkUsageResponse>,
+    pub interface: Option<String>,
+    pub dns_servers: Vec<String>,
+    pub vlan_id: Option<i32>,
+    pub region: Option<String>,
+}
+
+fn network_usage(
+    cidr: String,
+    gateway: String,
+    allocated: &HashSet<IpAddr>,
+    reservations: &[IpReservationRange],
+) -> anyhow::Result<IpNetworkUsageResponse> {
+    let usage = IpPoolNetwork::parse(&cidr, &gateway)?.usage(allocated, reservations);
+    Ok(IpNetworkUsageResponse {
+        cidr,
+        gateway,
+        total: usage.total,
+        allocated: usage.allocated,
+        reserved: usage.reserved,
+        available: usage.available,
+    })
+}
+
+// IPプールごとの使用状況を取得します。
+pub async fn get_ip_pool_usage(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<IpPoolResponse>>> {
+    let mut response = Vec::new();
+    for ip_pool in get_ip_pools(&state.db_pool).await? {
+        let allocated: HashSet<IpAddr> = ip_pool
+            .allocated
+            .iter()
+            .filter_map(|address| host_address(address))
+            .collect();
+        let reservations: Vec<IpReservationRange> = ip_pool
+            .reservations
+            .iter()
+            .filter_map(|(start, end)| IpReservationRange::parse(start, end))
+            .collect();
+        let ipv6 = match (ip_pool.cidr6, ip_pool.gateway6) {
+            (Some(cidr6), Some(gateway6)) => {
+                Some(network_usage(cidr6, gateway6, &allocated, &reservations)?)
+            }
+            _ => None,
+        };
+        response.push(IpPoolResponse {
+            id: ip_pool.id,
+            name: ip_pool.name,
+            ipv4: network_usage(ip_pool.cidr, ip_pool.gateway, &allocated, &reservations)?,
+            ipv6,
+            interface: ip_pool.interface,
+            dns_servers: ip_pool.dns_servers,
+            vlan_id: ip_pool.vlan_id,
+            region: ip_pool.region,
+        });
+    }
+    Ok(Json(response))
+}
+
+#[derive(Deserialize)]
+pub struct IpPoolNetworkRequest {
+    pub name: String,
+    // サーバーを接続するブリッジのインターフェース名です。
+    pub interface: String,
+    #[serde(default)]
+    pub dns_servers: Vec<String>,
+    pub vlan_id: Option<i32>,
+    pub region: Option<String>,
+}
+
+// 接続先の設定を検証し、DNSサーバーのアドレスを正規化します。
+impl IpPoolNetworkRequest {
+    fn validate(self) -> APIResult<Self> {
+        if self.name.trim().is_empty() || self.interface.trim().is_empty() {
+            return Err(APIError::bad_request("Name and interface are required"));
+        }
+        if self
+            .vlan_id
+            .is_some_and(|vlan_id| !(1..=4094).contains(&vlan_id))
+        {
+            return Err(APIError::bad_request("VLAN ID must be between 1 and 4094"));
+        }
+        let dns_servers = parse_dns_servers(&self.dns_servers.join(","))
+            .map_err(|_| APIError::bad_request("Invalid DNS server address"))?;
+        Ok(Self {
+            dns_servers,
+            region: self.region.filter(|region| !region.trim().is_empty()),
+            ..self
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateIpPoolRequest {
+    pub cidr: String,
+    pub gateway: String,
+    // IPv6のアドレスも割り当てる場合は、両方を指定します。
+    pub cidr6: Option<String>,
+    pub gateway6: Option<String>,
+    #[serde(flatten)]
+    pub network: IpPoolNetworkRequest,
+}
+
+#[derive(Serialize)]
+pub struct CreateIpPoolResponse {
+    pub id: i32,
+}
+
+pub async fn create_ip_pool(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Json(payload): Json<CreateIpPoolRequest>,
+) -> APIResult<Json<CreateIpPoolResponse>> {
+    let ipv4 = IpPoolNetwork::parse(&payload.cidr, &payload.gateway)
+        .map_err(|_| APIError::bad_request("Invalid IPv4 network or gateway"))?;
+    if !matches!(ipv4.net, IpNet::V4(_)) {
+        return Err(APIError::bad_request("Invalid IPv4 network or gateway"));
+    }
+    match (&payload.cidr6, &payload.gateway6) {
+        (Some(cidr6), Some(gateway6)) => {
+            let ipv6 = IpPoolNetwork::parse(cidr6, gateway6)
+                .map_err(|_| APIError::bad_request("Invalid IPv6 network or gateway"))?;
+            if !matches!(ipv6.net, IpNet::V6(_)) {
+                return Err(APIError::bad_request("Invalid IPv6 network or gateway"));
+            }
+        }
+        (None, None) => {}
+        _ => {
+            return Err(APIError::bad_request(
+                "cidr6 and gateway6 must be specified together",
+            ));
+        }
+    }
+    let network = payload.network.validate()?;
+    let spec = IpPoolSpec {
+        name: network.name,
+        cidr: ipv4.net.to_string(),
+        gateway: ipv4.gateway.to_string(),
+        cidr6: payload.cidr6,
+        gateway6: payload.gateway6,
+        interface: network.interface,
+        dns_servers: network.dns_servers,
+        vlan_id: network.vlan_id,
+        region: network.region,
+    };
+    let id = add_ip_pool(&state.db_pool, spec)
+        .await?
+        .ok_or_else(|| APIError::bad_request("IP pool name already exists"))?;
+    Ok(Json(CreateIpPoolResponse { id }))
+}
+
+// 接続先の設定のみ変更できます。アドレスの範囲を変える場合は新しいプールを作成します。
+pub async fn put_ip_pool(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((pool_id,)): Path<(i32,)>,
+    Json(payload): Json<IpPoolNetworkRequest>,
+) -> APIResult<()> {
+    let network = payload.validate()?;
+    match set_ip_pool(
+        &state.db_pool,
+        pool_id,
+        network.name,
+        network.interface,
+        network.dns_servers,
+        network.vlan_id,
+        network.region,
+    )
+    .await?
+    {
+        Some(true) => Ok(()),
+        Some(false) => Err(APIError::bad_request("IP pool name already exists")),
+        None => Err(APIError::not_found("IP pool not found")),
+    }
+}
+
+pub async fn remove_ip_pool(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((pool_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    match delete_ip_pool(&state.db_pool, pool_id).await? {
+        IpPoolDeletion::Deleted => Ok(()),
+        IpPoolDeletion::UsedByPlan => Err(APIError::bad_request("IP pool is in use by a plan")),
+        IpPoolDeletion::UsedByServer => Err(APIError::bad_request("IP pool is in use by a server")),
+        IpPoolDeletion::NotFound => Err(APIError::not_found("IP pool not found")),
+    }
+}
+
+#[derive(Serialize)]
+pub struct IpReservationResponse {
+    pub id: i32,
+    pub start_address: String,
+    pub end_address: String,
+    pub note: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+pub async fn get_reservations(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((pool_id,)): Path<(i32,)>,
+) -> APIResult<Json<Vec<IpReservationResponse>>> {
+    if get_ip_pool(&state.db_pool, pool_id).await?.is_none() {
+        return Err(APIError::not_found("IP pool not found"));
+    }
+    let reservations = get_ip_reservations(&state.db_pool, pool_id)
+        .await?
+        .into_iter()
+        .map(
+            |(id, start_address, end_address, note, created_at)| IpReservationResponse {
+                id,
+                start_address,
+                end_address,
+                note,
+                created_at,
+            },
+        )
+        .collect();
+    Ok(Json(reservations))
+}
+
+#[derive(Deserialize)]
+pub struct CreateIpReservationRequest {
+    pub start_address: String,
+    // 省略した場合はstart_addressの1つだけを予約します。
+    pub end_address: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateIpReservationResponse {
+    pub id: i32,
+}
+
+// 割り当てに使わないアドレスの範囲を登録します。既に割り当て済みのアドレスはそのまま使われます。
+pub async fn create_reservation(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((pool_id,)): Path<(i32,)>,
+    Json(payload): Json<CreateIpReservationRequest>,
+) -> APIResult<Json<CreateIpReservationResponse>> {
+    let (cidr, cidr6) = get_ip_pool(&state.db_pool, pool_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("IP pool not found"))?;
+    let end_address = payload
+        .end_address
+        .unwrap_or_else(|| payload.start_address.clone());
+    let range = IpReservationRange::parse(&payload.start_address, &end_address)
+        .ok_or_else(|| APIError::bad_request("Invalid address range"))?;
+    let in_pool = [Some(cidr), cidr6]
+        .into_iter()
+        .flatten()
+        .map(|cidr| cidr.parse::<IpNet>())
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .iter()
+        .any(|net| net.contains(range.start) && net.contains(range.end));
+    if !in_pool {
+        return Err(APIError::bad_request(
+            "Address range is outside of the IP pool",
+        ));
+    }
+    let id = add_ip_reservation(
+        &state.db_pool,
+        pool_id,
+        range.start.to_string(),
+        range.end.to_string(),
+        payload.note,
+    )
+    .await?;
+    Ok(Json(CreateIpReservationResponse { id }))
+}
+
+pub async fn remove_reservation(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((pool_id, reservation_id)): Path<(i32, i32)>,
+) -> APIResult<()> {
+    if !delete_ip_reservation(&state.db_pool, pool_id, reservation_id).await? {
+        return Err(APIError::not_found("IP reservation not found"));
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::{
+    db::server_job::get_server_job,
+    error::{APIError, APIResult},
+    state::AppState,
+    token::{ORG_READ_ROLES, SCOPE_SERVERS_READ, Token},
+};
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    pub id: i32,
+    pub server_id: String,
+    pub kind: String,
+    // pending、running、succeeded、failedのいずれかです。
+    pub status: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+// サーバーの作成などのジョブの進み具合を取得します。
+pub async fn get_job(
+    State(state): State<AppState>,
+    token: Token,
+    Path((job_id,)): Path<(i32,)>,
+) -> APIResult<Json<JobResponse>> {
+    token.require_scope(SCOPE_SERVERS_READ)?;
+    let job = get_server_job(&state.db_pool, job_id, token.user_id, ORG_READ_ROLES)
+        .await?
+        .ok_or_else(|| APIError::not_found("Job not found"))?;
+    Ok(Json(JobResponse {
+        id: job.id,
+        server_id: job.server_id,
+        kind: job.kind,
+        status: job.status,
+        attempts: job.attempts,
+        last_error: job.last_error,
+        created_at: job.created_at,
+        updated_at: job.updated_at,
+    }))
+}
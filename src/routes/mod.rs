@@ -1,3 +1,17 @@
+pub mod admin;
+pub mod api_key;
+pub mod audit_log;
+pub mod avatar;
+pub mod invite_code;
+pub mod ipam;
+pub mod job;
+pub mod oidc;
+pub mod organization;
+pub mod plan;
+pub mod quota;
 pub mod server;
 pub mod setup_script;
+pub mod ssh_key;
+pub mod totp;
 pub mod user;
+pub mod webauthn;
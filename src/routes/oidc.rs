@@ -0,0 +1,215 @@
+use std::env;
+
+use axum::{Json, extract::State};
+use bb8_redis::redis::AsyncCommands;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client_info::ClientInfo,
+    db::{
+        identity::{add_identity, get_user_by_identity},
+        user::{add_user, exist_username, get_user_credentials_by_email},
+    },
+    error::{APIError, APIResult},
+    routes::user::{IssueUserTokenResponseModel, complete_login},
+    state::AppState,
+    utils::{
+        audit::{AUDIT_USER_LOGIN, audit_result, user_target},
+        oidc::{OidcConfig, VerifiedIdentity, create_authorization_request, exchange_code},
+    },
+};
+
+const OIDC_LOGIN_TTL: u64 = 600;
+
+fn oidc_config() -> APIResult<OidcConfig> {
+    OidcConfig::from_env()?.ok_or_else(|| APIError::not_found("OIDC login is not configured"))
+}
+
+// IDプロバイダーから戻ってくるまでの間、Redisに保存しておく値です。
+#[derive(Deserialize, Serialize)]
+struct PendingOidcLogin {
+    nonce: String,
+    pkce_verifier: String,
+}
+
+#[derive(Serialize)]
+pub struct OidcAuthorizeResponseModel {
+    pub authorization_url: String,
+}
+
+// IDプロバイダーの認可URLを発行します。
+pub async fn oidc_authorize(
+    State(state): State<AppState>,
+) -> APIResult<Json<OidcAuthorizeResponseModel>> {
+    let config = oidc_config()?;
+    let request = create_authorization_request(&config).await?;
+    {
+        let mut conn = state.redis_pool.get().await?;
+        let key = format!("oidc_login:{}", request.state);
+        let value = serde_json::to_string(&PendingOidcLogin {
+            nonce: request.nonce,
+            pkce_verifier: request.pkce_verifier,
+        })?;
+        let _: () = conn.set_ex(key, value, OIDC_LOGIN_TTL).await?;
+    }
+    Ok(Json(OidcAuthorizeResponseModel {
+        authorization_url: request.url,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackRequestModel {
+    pub code: String,
+    pub state: String,
+}
+
+// 重複しないユーザー名を決めます。
+async fn allocate_username(
+    state: &AppState,
+    identity: &VerifiedIdentity,
+) -> anyhow::Result<String> {
+    let base: String = identity
+        .preferred_username
+        .as_deref()
+        .or_else(|| identity.email.as_deref()?.split('@').next())
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        .take(40)
+        .collect();
+    let base = if base.is_empty() {
+        "user".to_string()
+    } else {
+        base
+    };
+    if !exist_username(&state.db_pool, base.clone()).await? {
+        return Ok(base);
+    }
+    for _ in 0..10 {
+        let candidate = format!("{base}-{:04}", rand::rng().random_range(0..10000));
+        if !exist_username(&state.db_pool, candidate.clone()).await? {
+            return Ok(candidate);
+        }
+    }
+    anyhow::bail!("Failed to allocate a username")
+}
+
+// 一意制約に違反した場合は、その制約の名前を返します。
+fn violated_unique_constraint(e: &anyhow::Error) -> Option<&str> {
+    match e.downcast_ref::<sqlx::Error>()? {
+        sqlx::Error::Database(e) if e.is_unique_violation() => e.constraint(),
+        _ => None,
+    }
+}
+
+// ユーザーを作成します。同時に登録されてユーザー名が重複した場合は、別の名前で登録し直します。
+async fn add_oidc_user(
+    state: &AppState,
+    identity: &VerifiedIdentity,
+    email: String,
+) -> APIResult<i32> {
+    for _ in 0..3 {
+        let username = allocate_username(state, identity).await?;
+        match add_user(&state.db_pool, username, email.clone(), None, None).await {
+            Ok(user_id) => {
+                return Ok(user_id.ok_or_else(|| anyhow::anyhow!("Failed to add user"))?);
+            }
+            Err(e) => match violated_unique_constraint(&e) {
+                Some("users_username_key") => continue,
+                Some("users_email_key") => {
+                    return Err(APIError::conflict("The account is already being created"));
+                }
+                _ => return Err(e.into()),
+            },
+        }
+    }
+    Err(APIError::conflict("Failed to allocate a username"))
+}
+
+// IDプロバイダーのアカウントに対応するユーザーを探し、なければ紐づけるか作成します。
+async fn resolve_user(state: &AppState, identity: &VerifiedIdentity) -> APIResult<(i32, bool)> {
+    if let Some(user) = get_user_by_identity(
+        &state.db_pool,
+        identity.issuer.clone(),
+        identity.subject.clone(),
+    )
+    .await?
+    {
+        return Ok(user);
+    }
+
+    // 検証されていないメールアドレスで既存のユーザーを乗っ取られないようにします。
+    let email = match &identity.email {
+        Some(email) if identity.email_verified => email.trim().to_lowercase(),
+        _ => {
+            return Err(APIError::forbidden(
+                "The identity provider did not return a verified email",
+            ));
+        }
+    };
+    let (user_id, suspended) = if let Some((user_id, _, suspended)) =
+        get_user_credentials_by_email(&state.db_pool, email.clone()).await?
+    {
+        (user_id, suspended)
+    } else {
+        // 招待コードなしで登録できてしまうため、明示的に許可した場合のみ作成します。
+        if !env::var("OIDC_ALLOW_SIGNUP").is_ok_and(|v| v == "true") {
+            return Err(APIError::forbidden("Sign up with OIDC is disabled"));
+        }
+        (add_oidc_user(state, identity, email).await?, false)
+    };
+    add_identity(
+        &state.db_pool,
+        user_id,
+        identity.issuer.clone(),
+        identity.subject.clone(),
+    )
+    .await?;
+    Ok((user_id, suspended))
+}
+
+// IDプロバイダーから戻ってきた認可コードを検証し、ログインします。
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<OidcCallbackRequestModel>,
+) -> APIResult<Json<IssueUserTokenResponseModel>> {
+    let mut target_user = None;
+    let result: APIResult<IssueUserTokenResponseModel> = async {
+        let config = oidc_config()?;
+        let pending: PendingOidcLogin = {
+            let mut conn = state.redis_pool.get().await?;
+            let value: Option<String> = conn
+                .get_del(format!("oidc_login:{}", payload.state))
+                .await?;
+            match value {
+                Some(value) => serde_json::from_str(&value)?,
+                None => return Err(APIError::gone("OIDC login expired")),
+            }
+        };
+        let identity = exchange_code(&config, payload.code, pending.nonce, pending.pkce_verifier)
+            .await
+            .map_err(|e| {
+                tracing::warn!("Failed to verify OIDC login: {}", e);
+                APIError::unauthorized("Failed to verify the identity provider response")
+            })?;
+        let (user_id, suspended) = resolve_user(&state, &identity).await?;
+        target_user = Some(user_id);
+        if suspended {
+            return Err(APIError::forbidden("Account is suspended"));
+        }
+        complete_login(&state, user_id, client.clone()).await
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        target_user.filter(|_| result.is_ok()),
+        AUDIT_USER_LOGIN,
+        target_user.map(user_target),
+        &result,
+    )
+    .await;
+    Ok(Json(result?))
+}
@@ -0,0 +1,336 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use bb8_redis::redis::AsyncCommands;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        organization::{
+            add_member, add_organization, count_owners, delete_member, delete_organization,
+            get_member_role, get_members, get_organization, get_organizations_by_user,
+            set_member_role,
+        },
+        server::get_servers_by_organization,
+        user::get_userdata_by_id,
+    },
+    error::{APIError, APIResult},
+    routes::server::{GetServerResponse, with_status},
+    state::AppState,
+    token::{ORG_MANAGE_ROLES, ORG_READ_ROLES, ORG_ROLE_OWNER, SCOPE_SERVERS_READ, Token},
+    utils::{mail::send_organization_invite, passcode::generate_secret_token},
+};
+
+const ORGANIZATION_INVITE_TTL: u64 = 60 * 60 * 24 * 7;
+
+// ユーザーが組織で指定したロールのいずれかを持っていることを確認し、そのロールを返します。
+// 所属していない組織は存在しないものとして扱います。
+pub async fn require_org_role(
+    state: &AppState,
+    organization_id: i32,
+    user_id: i32,
+    roles: &[&str],
+) -> APIResult<String> {
+    let role = get_member_role(&state.db_pool, organization_id, user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Organization not found"))?;
+    if !roles.contains(&role.as_str()) {
+        return Err(APIError::forbidden("Insufficient organization role"));
+    }
+    Ok(role)
+}
+
+fn validate_role(role: &str) -> APIResult<()> {
+    if !ORG_READ_ROLES.contains(&role) {
+        return Err(APIError::bad_request("Unknown role"));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct CreateOrganizationResponse {
+    pub id: i32,
+}
+
+// 組織を作成します。作成したユーザーがオーナーになります。
+pub async fn create_organization(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<CreateOrganizationRequest>,
+) -> APIResult<Json<CreateOrganizationResponse>> {
+    token.require_session()?;
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err(APIError::bad_request("Name is required"));
+    }
+    let id = add_organization(&state.db_pool, name, token.user_id).await?;
+    Ok(Json(CreateOrganizationResponse { id }))
+}
+
+#[derive(Serialize)]
+pub struct GetOrganizationResponse {
+    pub id: i32,
+    pub name: String,
+    pub role: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// 所属している組織の一覧を取得します。
+pub async fn get_my_organizations(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<GetOrganizationResponse>>> {
+    token.require_session()?;
+    let organizations = get_organizations_by_user(&state.db_pool, token.user_id)
+        .await?
+        .into_iter()
+        .map(|(id, name, role, created_at)| GetOrganizationResponse {
+            id,
+            name,
+            role,
+            created_at,
+        })
+        .collect();
+    Ok(Json(organizations))
+}
+
+#[derive(Serialize)]
+pub struct OrganizationMemberResponse {
+    pub user_id: i32,
+    pub username: String,
+    pub email: String,
+    pub role: String,
+    pub joined_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct GetOrganizationDetailResponse {
+    pub id: i32,
+    pub name: String,
+    pub role: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub members: Vec<OrganizationMemberResponse>,
+}
+
+pub async fn get_organization_detail(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id,)): Path<(i32,)>,
+) -> APIResult<Json<GetOrganizationDetailResponse>> {
+    token.require_session()?;
+    let role = require_org_role(&state, organization_id, token.user_id, ORG_READ_ROLES).await?;
+    let (name, created_at) = get_organization(&state.db_pool, organization_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Organization not found"))?;
+    let members = get_members(&state.db_pool, organization_id)
+        .await?
+        .into_iter()
+        .map(
+            |(user_id, username, email, role, joined_at)| OrganizationMemberResponse {
+                user_id,
+                username,
+                email,
+                role,
+                joined_at,
+            },
+        )
+        .collect();
+    Ok(Json(GetOrganizationDetailResponse {
+        id: organization_id,
+        name,
+        role,
+        created_at,
+        members,
+    }))
+}
+
+// 組織を削除します。サーバーが残っている場合は削除できません。
+pub async fn remove_organization(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    require_org_role(&state, organization_id, token.user_id, &[ORG_ROLE_OWNER]).await?;
+    if !get_servers_by_organization(&state.db_pool, organization_id)
+        .await?
+        .is_empty()
+    {
+        return Err(APIError::bad_request(
+            "Delete the organization's servers first",
+        ));
+    }
+    delete_organization(&state.db_pool, organization_id).await?;
+    Ok(())
+}
+
+// 組織のサーバーの一覧を取得します。
+pub async fn get_organization_servers(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id,)): Path<(i32,)>,
+) -> APIResult<Json<Vec<GetServerResponse>>> {
+    token.require_scope(SCOPE_SERVERS_READ)?;
+    require_org_role(&state, organization_id, token.user_id, ORG_READ_ROLES).await?;
+    let servers = get_servers_by_organization(&state.db_pool, organization_id).await?;
+    Ok(Json(with_status(&state.db_pool, servers).await?))
+}
+
+#[derive(Deserialize)]
+pub struct InviteMemberRequest {
+    pub email: String,
+    pub role: String,
+}
+
+// 承諾を待っている組織への招待です。
+#[derive(Deserialize, Serialize)]
+struct PendingOrganizationInvite {
+    organization_id: i32,
+    email: String,
+    role: String,
+}
+
+// メールアドレス宛てに組織への招待を送ります。
+pub async fn invite_member(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id,)): Path<(i32,)>,
+    Json(payload): Json<InviteMemberRequest>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let role = require_org_role(&state, organization_id, token.user_id, ORG_MANAGE_ROLES).await?;
+    validate_role(&payload.role)?;
+    if payload.role == ORG_ROLE_OWNER && role != ORG_ROLE_OWNER {
+        return Err(APIError::forbidden("Only owners can invite owners"));
+    }
+    let (name, _) = get_organization(&state.db_pool, organization_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Organization not found"))?;
+    let invite_token = generate_secret_token()?;
+    send_organization_invite(name, invite_token.clone(), payload.email.clone()).await?;
+    let mut conn = state.redis_pool.get().await?;
+    let _: () = conn
+        .set_ex(
+            format!("organization_invite:{invite_token}"),
+            serde_json::to_string(&PendingOrganizationInvite {
+                organization_id,
+                email: payload.email,
+                role: payload.role,
+            })?,
+            ORGANIZATION_INVITE_TTL,
+        )
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInviteRequest {
+    pub token: String,
+}
+
+// 招待を受け入れて組織に参加します。招待されたメールアドレスのアカウントでのみ受け入れられます。
+pub async fn accept_invite(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<AcceptInviteRequest>,
+) -> APIResult<Json<CreateOrganizationResponse>> {
+    token.require_session()?;
+    let mut conn = state.redis_pool.get().await?;
+    let key = format!("organization_invite:{}", payload.token);
+    let invite: PendingOrganizationInvite = {
+        let value: Option<String> = conn.get(&key).await?;
+        let value = value.ok_or_else(|| APIError::gone("Invite expired"))?;
+        serde_json::from_str(&value)?
+    };
+    let (_, email) = get_userdata_by_id(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("User not found"))?;
+    if !email.eq_ignore_ascii_case(invite.email.trim()) {
+        return Err(APIError::forbidden("This invite is for a different email"));
+    }
+    // 招待は一度しか使えません。
+    let consumed: Option<String> = conn.get_del(&key).await?;
+    if consumed.is_none() {
+        return Err(APIError::gone("Invite expired"));
+    }
+    if !add_member(
+        &state.db_pool,
+        invite.organization_id,
+        token.user_id,
+        invite.role,
+    )
+    .await?
+    {
+        return Err(APIError::bad_request("Already a member"));
+    }
+    Ok(Json(CreateOrganizationResponse {
+        id: invite.organization_id,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SetMemberRoleRequest {
+    pub role: String,
+}
+
+// メンバーのロールを変更します。オーナーに関わる変更はオーナーのみが行えます。
+pub async fn put_member_role(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id, user_id)): Path<(i32, i32)>,
+    Json(payload): Json<SetMemberRoleRequest>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let role = require_org_role(&state, organization_id, token.user_id, ORG_MANAGE_ROLES).await?;
+    validate_role(&payload.role)?;
+    let current = get_member_role(&state.db_pool, organization_id, user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Member not found"))?;
+    if (payload.role == ORG_ROLE_OWNER || current == ORG_ROLE_OWNER) && role != ORG_ROLE_OWNER {
+        return Err(APIError::forbidden("Only owners can change owners"));
+    }
+    if current == ORG_ROLE_OWNER
+        && payload.role != ORG_ROLE_OWNER
+        && count_owners(&state.db_pool, organization_id).await? <= 1
+    {
+        return Err(APIError::bad_request(
+            "An organization needs at least one owner",
+        ));
+    }
+    set_member_role(&state.db_pool, organization_id, user_id, payload.role).await?;
+    Ok(())
+}
+
+// メンバーを組織から外します。自分自身であればロールに関係なく脱退できます。
+pub async fn remove_member(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id, user_id)): Path<(i32, i32)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let role = if user_id == token.user_id {
+        None
+    } else {
+        Some(require_org_role(&state, organization_id, token.user_id, ORG_MANAGE_ROLES).await?)
+    };
+    let current = get_member_role(&state.db_pool, organization_id, user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Member not found"))?;
+    if current == ORG_ROLE_OWNER && role.is_some_and(|role| role != ORG_ROLE_OWNER) {
+        return Err(APIError::forbidden("Only owners can remove owners"));
+    }
+    if current == ORG_ROLE_OWNER && count_owners(&state.db_pool, organization_id).await? <= 1 {
+        return Err(APIError::bad_request(
+            "An organization needs at least one owner",
+        ));
+    }
+    delete_member(&state.db_pool, organization_id, user_id).await?;
+    Ok(())
+}
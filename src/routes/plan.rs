@@ -0,0 +1,153 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        ipam::get_ip_pool,
+        plan::{
+            PLAN_STATUS_ACTIVE, PLAN_STATUSES, PlanSpec, add_plan, delete_plan, get_plans, set_plan,
+        },
+    },
+    error::{APIError, APIResult},
+    state::AppState,
+    token::AdminToken,
+};
+
+#[derive(Serialize)]
+pub struct AdminPlanResponse {
+    pub id: i32,
+    pub name: String,
+    pub cpu: i32,
+    pub memory: i32,
+    pub disk: i32,
+    pub bandwidth: i32,
+    pub price: i32,
+    pub status: String,
+    pub ip_pool_id: Option<i32>,
+}
+
+// 非公開や提供終了のものも含め、すべてのプランを取得します。
+pub async fn get_all_plans(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<AdminPlanResponse>>> {
+    let plans = get_plans(&state.db_pool, None)
+        .await?
+        .into_iter()
+        .map(|plan| AdminPlanResponse {
+            id: plan.id,
+            name: plan.name,
+            cpu: plan.cpu,
+            memory: plan.memory,
+            disk: plan.disk,
+            bandwidth: plan.bandwidth,
+            price: plan.price,
+            status: plan.status,
+            ip_pool_id: plan.ip_pool_id,
+        })
+        .collect();
+    Ok(Json(plans))
+}
+
+#[derive(Deserialize)]
+pub struct PlanRequest {
+    pub name: String,
+    pub cpu: i32,
+    pub memory: i32,
+    pub disk: i32,
+    pub bandwidth: i32,
+    pub price: i32,
+    pub status: Option<String>,
+    // 指定した場合は、このプランのサーバーにこのプールのアドレスを割り当てます。
+    pub ip_pool_id: Option<i32>,
+}
+
+impl PlanRequest {
+    async fn into_spec(self, state: &AppState) -> APIResult<PlanSpec> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(APIError::bad_request("Plan name must not be empty"));
+        }
+        if self.cpu <= 0 || self.memory <= 0 || self.disk <= 0 {
+            return Err(APIError::bad_request(
+                "cpu, memory and disk must be positive",
+            ));
+        }
+        if self.bandwidth < 0 || self.price < 0 {
+            return Err(APIError::bad_request(
+                "bandwidth and price must not be negative",
+            ));
+        }
+        let status = self
+            .status
+            .unwrap_or_else(|| PLAN_STATUS_ACTIVE.to_string());
+        if !PLAN_STATUSES.contains(&status.as_str()) {
+            return Err(APIError::bad_request("Unknown plan status"));
+        }
+        if let Some(ip_pool_id) = self.ip_pool_id
+            && get_ip_pool(&state.db_pool, ip_pool_id).await?.is_none()
+        {
+            return Err(APIError::bad_request("Unknown IP pool"));
+        }
+        Ok(PlanSpec {
+            name,
+            cpu: self.cpu,
+            memory: self.memory,
+            disk: self.disk,
+            bandwidth: self.bandwidth,
+            price: self.price,
+            status,
+            ip_pool_id: self.ip_pool_id,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct CreatePlanResponse {
+    pub id: i32,
+}
+
+pub async fn create_plan(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Json(payload): Json<PlanRequest>,
+) -> APIResult<Json<CreatePlanResponse>> {
+    let id = add_plan(&state.db_pool, payload.into_spec(&state).await?)
+        .await?
+        .ok_or_else(|| APIError::bad_request("A plan with this name already exists"))?;
+    Ok(Json(CreatePlanResponse { id }))
+}
+
+// プランを更新します。提供を終了する場合はstatusをretiredにします。
+pub async fn put_plan(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((plan_id,)): Path<(i32,)>,
+    Json(payload): Json<PlanRequest>,
+) -> APIResult<()> {
+    match set_plan(&state.db_pool, plan_id, payload.into_spec(&state).await?).await? {
+        Some(true) => Ok(()),
+        Some(false) => Err(APIError::bad_request(
+            "A plan with this name already exists",
+        )),
+        None => Err(APIError::not_found("Plan not found")),
+    }
+}
+
+// 使っているサーバーがないプランのみ削除できます。
+pub async fn remove_plan(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((plan_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    match delete_plan(&state.db_pool, plan_id).await? {
+        Some(true) => Ok(()),
+        Some(false) => Err(APIError::bad_request(
+            "Plan is used by servers; retire it instead",
+        )),
+        None => Err(APIError::not_found("Plan not found")),
+    }
+}
@@ -0,0 +1,243 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::{
+        organization::get_organization,
+        quota::{
+            QuotaLimits, delete_organization_quota_override, delete_user_quota_override,
+            get_organization_quota, get_quota_defaults, get_quota_usage, get_user_quota,
+            set_organization_quota_override, set_quota_default, set_user_quota_override,
+        },
+        user::get_user_role,
+    },
+    error::{APIError, APIResult},
+    routes::{organization::require_org_role, server::ServerPlanResource},
+    state::AppState,
+    token::{AdminToken, ORG_READ_ROLES, SCOPE_SERVERS_READ, Token},
+};
+
+// クォータを適用する単位です。組織のサーバーは組織のクォータに数えます。
+#[derive(Clone, Copy)]
+pub enum QuotaOwner {
+    User(i32),
+    Organization(i32),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct QuotaResources {
+    pub servers: i32,
+    pub cpu: i32,
+    pub memory: i32,
+    pub disk: i32,
+}
+
+impl From<QuotaLimits> for QuotaResources {
+    fn from((servers, cpu, memory, disk): QuotaLimits) -> Self {
+        Self {
+            servers,
+            cpu,
+            memory,
+            disk,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct QuotaResponse {
+    pub limits: QuotaResources,
+    pub usage: QuotaResources,
+}
+
+// 上限と、所有しているサーバーのプランから計算した使用量を取得します。
+async fn get_quota(state: &AppState, owner: QuotaOwner) -> anyhow::Result<QuotaResponse> {
+    let (limits, usage) = match owner {
+        QuotaOwner::User(user_id) => (
+            get_user_quota(&state.db_pool, user_id).await?,
+            get_quota_usage(&*state.db_pool, user_id, None).await?,
+        ),
+        QuotaOwner::Organization(organization_id) => (
+            get_organization_quota(&state.db_pool, organization_id).await?,
+            get_quota_usage(&*state.db_pool, 0, Some(organization_id)).await?,
+        ),
+    };
+    Ok(QuotaResponse {
+        limits: limits.into(),
+        usage: usage.into(),
+    })
+}
+
+// 指定したプランのサーバーを追加しても上限を超えないことを確認し、上限を返します。
+// 同時に作成された場合の確認は、サーバーを登録するトランザクションの中で改めて行います。
+pub async fn check_quota(
+    state: &AppState,
+    owner: QuotaOwner,
+    resources: &ServerPlanResource,
+) -> APIResult<QuotaLimits> {
+    let QuotaResponse { limits, usage } = get_quota(state, owner).await?;
+    let exceeded = [
+        ("servers", usage.servers + 1, limits.servers),
+        ("cpu", usage.cpu + resources.cpu, limits.cpu),
+        ("memory", usage.memory + resources.memory, limits.memory),
+        ("disk", usage.disk + resources.disk, limits.disk),
+    ]
+    .into_iter()
+    .find(|(_, requested, limit)| requested > limit);
+    if let Some((name, _, limit)) = exceeded {
+        return Err(APIError::forbidden(&format!(
+            "Quota exceeded: {name} (limit {limit})"
+        )));
+    }
+    Ok((limits.servers, limits.cpu, limits.memory, limits.disk))
+}
+
+// 自分のクォータと使用量を取得します。
+pub async fn get_my_quota(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<QuotaResponse>> {
+    token.require_scope(SCOPE_SERVERS_READ)?;
+    Ok(Json(
+        get_quota(&state, QuotaOwner::User(token.user_id)).await?,
+    ))
+}
+
+pub async fn get_organization_quota_usage(
+    State(state): State<AppState>,
+    token: Token,
+    Path((organization_id,)): Path<(i32,)>,
+) -> APIResult<Json<QuotaResponse>> {
+    token.require_scope(SCOPE_SERVERS_READ)?;
+    require_org_role(&state, organization_id, token.user_id, ORG_READ_ROLES).await?;
+    Ok(Json(
+        get_quota(&state, QuotaOwner::Organization(organization_id)).await?,
+    ))
+}
+
+#[derive(Serialize)]
+pub struct QuotaDefaultResponse {
+    pub scope: String,
+    pub limits: QuotaResources,
+}
+
+pub async fn get_default_quotas(
+    State(state): State<AppState>,
+    _token: AdminToken,
+) -> APIResult<Json<Vec<QuotaDefaultResponse>>> {
+    let defaults = get_quota_defaults(&state.db_pool)
+        .await?
+        .into_iter()
+        .map(|(scope, limits)| QuotaDefaultResponse {
+            scope,
+            limits: limits.into(),
+        })
+        .collect();
+    Ok(Json(defaults))
+}
+
+fn validate_limits(limits: &[Option<i32>]) -> APIResult<()> {
+    if limits.iter().flatten().any(|limit| *limit < 0) {
+        return Err(APIError::bad_request("Limits must not be negative"));
+    }
+    Ok(())
+}
+
+// ユーザーまたは組織の既定のクォータを変更します。
+pub async fn put_default_quota(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((scope,)): Path<(String,)>,
+    Json(payload): Json<QuotaResources>,
+) -> APIResult<()> {
+    validate_limits(&[
+        Some(payload.servers),
+        Some(payload.cpu),
+        Some(payload.memory),
+        Some(payload.disk),
+    ])?;
+    if !set_quota_default(
+        &state.db_pool,
+        scope,
+        (payload.servers, payload.cpu, payload.memory, payload.disk),
+    )
+    .await?
+    {
+        return Err(APIError::not_found("Unknown quota scope"));
+    }
+    Ok(())
+}
+
+// 省略した項目は既定値を使います。
+#[derive(Deserialize)]
+pub struct QuotaOverrideRequest {
+    pub servers: Option<i32>,
+    pub cpu: Option<i32>,
+    pub memory: Option<i32>,
+    pub disk: Option<i32>,
+}
+
+pub async fn put_user_quota(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+    Json(payload): Json<QuotaOverrideRequest>,
+) -> APIResult<()> {
+    validate_limits(&[payload.servers, payload.cpu, payload.memory, payload.disk])?;
+    if get_user_role(&state.db_pool, user_id).await?.is_none() {
+        return Err(APIError::not_found("User not found"));
+    }
+    set_user_quota_override(
+        &state.db_pool,
+        user_id,
+        (payload.servers, payload.cpu, payload.memory, payload.disk),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_user_quota(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((user_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if !delete_user_quota_override(&state.db_pool, user_id).await? {
+        return Err(APIError::not_found("Quota override not found"));
+    }
+    Ok(())
+}
+
+pub async fn put_organization_quota(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((organization_id,)): Path<(i32,)>,
+    Json(payload): Json<QuotaOverrideRequest>,
+) -> APIResult<()> {
+    validate_limits(&[payload.servers, payload.cpu, payload.memory, payload.disk])?;
+    if get_organization(&state.db_pool, organization_id)
+        .await?
+        .is_none()
+    {
+        return Err(APIError::not_found("Organization not found"));
+    }
+    set_organization_quota_override(
+        &state.db_pool,
+        organization_id,
+        (payload.servers, payload.cpu, payload.memory, payload.disk),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_organization_quota(
+    State(state): State<AppState>,
+    _token: AdminToken,
+    Path((organization_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    if !delete_organization_quota_override(&state.db_pool, organization_id).await? {
+        return Err(APIError::not_found("Quota override not found"));
+    }
+    Ok(())
+}
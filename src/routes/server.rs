@@ -1,28 +1,46 @@
-use std::env;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 use axum::{
     Json,
     extract::{Path, State},
+    http::StatusCode,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 
 use crate::{
+    client_info::ClientInfo,
     db::{
+        ipam::{
+            AllocatedAddresses, allocate_ip_address, get_ip_pool_id, get_ip_pool_ids, get_regions,
+            release_ip_allocation,
+        },
+        plan::{PLAN_STATUS_ACTIVE, PLAN_STATUS_RETIRED, Plan, get_plan, get_plans},
         server::{
-            add_server, db_delete_server_by_id, db_get_server_by_id, get_all_servers_from_user,
-            get_server_ips,
+            NewServer, SERVER_STATUS_RUNNING, add_server, db_delete_server_by_id,
+            db_get_server_by_id, get_all_servers_from_user, get_domain_id, get_domain_ids,
         },
         setup_script::get_script_by_id,
+        ssh_key::get_public_keys_by_ids,
     },
     error::{APIError, APIResult},
+    routes::{
+        organization::require_org_role,
+        quota::{QuotaOwner, check_quota},
+    },
     state::AppState,
-    token::Token,
+    token::{ORG_READ_ROLES, ORG_WRITE_ROLES, SCOPE_SERVERS_READ, SCOPE_SERVERS_WRITE, Token},
     utils::{
         api::domain::{
-            self, CreateDomainRequest, CreateDomainRequestNetwork, CreateDomainRequestResources,
-            create_domain, fetch_all_servers, fetch_server,
+            self, CreateDomainRequest, CreateDomainRequestIpv6, CreateDomainRequestNetwork,
+            CreateDomainRequestResources, fetch_all_servers, fetch_server,
+        },
+        audit::{
+            AUDIT_SERVER_CREATE, AUDIT_SERVER_DELETE, AUDIT_SERVER_POWER_ON, AUDIT_SERVER_RESTART,
+            AUDIT_SERVER_SHUTDOWN, audit_result, server_target,
         },
-        ip_calc::cidr_to_list,
+        ipam::DEFAULT_IP_POOL,
+        provisioning::{ServerJobPayload, generate_server_id, store_server_password},
     },
 };
 
@@ -31,6 +49,7 @@ pub struct ServerPlanResource {
     pub cpu: i32,
     pub memory: i32,
     pub disk: i32,
+    pub bandwidth: i32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,6 +57,23 @@ pub struct ServerPlan {
     pub id: i32,
     pub name: String,
     pub resources: ServerPlanResource,
+    pub price: i32,
+}
+
+impl From<Plan> for ServerPlan {
+    fn from(plan: Plan) -> Self {
+        Self {
+            id: plan.id,
+            name: plan.name,
+            resources: ServerPlanResource {
+                cpu: plan.cpu,
+                memory: plan.memory,
+                disk: plan.disk,
+                bandwidth: plan.bandwidth,
+            },
+            price: plan.price,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -45,76 +81,218 @@ pub struct ServerPlansResponse {
     pub plans: Vec<ServerPlan>,
 }
 
-pub async fn get_server_plans() -> APIResult<Json<ServerPlansResponse>> {
-    let data: ServerPlansResponse = serde_json::from_str(include_str!("../../data.json"))?;
-    Ok(Json(data))
+// 申し込みできるプランの一覧を取得します。
+pub async fn get_server_plans(
+    State(state): State<AppState>,
+) -> APIResult<Json<ServerPlansResponse>> {
+    let plans = get_plans(&state.db_pool, Some(PLAN_STATUS_ACTIVE))
+        .await?
+        .into_iter()
+        .map(ServerPlan::from)
+        .collect();
+    Ok(Json(ServerPlansResponse { plans }))
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateServerRequest {
     pub name: String,
-    pub server_password: String,
+    pub server_password: Option<String>,
+    // サーバーに登録するSSHの公開鍵のIDです。
+    #[serde(default)]
+    pub ssh_key_ids: Vec<i32>,
+    // trueの場合、SSHでのパスワードログインを無効にします。
+    #[serde(default)]
+    pub disable_password_login: bool,
     pub plan: i32,
     pub script_id: Option<i32>,
+    // 指定した場合は、組織のサーバーとして作成します。
+    pub organization_id: Option<i32>,
+    // 指定した場合は、そのリージョンのプールからアドレスを割り当てます。
+    pub region: Option<String>,
+}
+
+// プランに指定されたプールか、リージョンのプールからアドレスを割り当てます。
+// どちらも指定されていない場合は既定のプールを使います。
+async fn allocate_server_addresses(
+    state: &AppState,
+    plan_ip_pool_id: Option<i32>,
+    region: Option<&str>,
+) -> APIResult<AllocatedAddresses> {
+    let ip_pool_ids = if plan_ip_pool_id.is_none() && region.is_none() {
+        let ip_pool_id = get_ip_pool_id(&state.db_pool, DEFAULT_IP_POOL)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Default IP pool is not registered"))?;
+        vec![ip_pool_id]
+    } else {
+        get_ip_pool_ids(&state.db_pool, plan_ip_pool_id, region).await?
+    };
+    if ip_pool_ids.is_empty() {
+        return Err(APIError::bad_request(
+            "This plan is not available in the requested region",
+        ));
+    }
+    for ip_pool_id in ip_pool_ids {
+        if let Some(addresses) = allocate_ip_address(&state.db_pool, ip_pool_id).await? {
+            return Ok(addresses);
+        }
+    }
+    Err(APIError::bad_request("No available IP addresses"))
+}
+
+#[derive(Serialize)]
+pub struct RegionsResponse {
+    pub regions: Vec<String>,
+}
+
+// サーバーを作成できるリージョンの一覧を取得します。
+pub async fn get_server_regions(State(state): State<AppState>) -> APIResult<Json<RegionsResponse>> {
+    Ok(Json(RegionsResponse {
+        regions: get_regions(&state.db_pool).await?,
+    }))
 }
 
+#[derive(Serialize)]
+pub struct CreateServerResponse {
+    pub id: String,
+    // 作成の進み具合は/jobs/{id}で確認できます。
+    pub job_id: i32,
+}
+
+// サーバーをprovisioningで登録して202を返します。VMコントローラーへの依頼はワーカーが行います。
 pub async fn create_server(
     State(state): State<AppState>,
     token: Token,
+    client: ClientInfo,
     Json(payload): Json<CreateServerRequest>,
-) -> APIResult<()> {
-    tracing::debug!("Creating server with payload: {:?}", payload);
-    let mut used_ips = get_server_ips(&state.db_pool).await?;
-    let (ips, prefix) = cidr_to_list(&env::var("NETWORK_CIDR")?)?;
-    used_ips.push(format!("{}/{}", env::var("NETWORK_GATEWAY")?, prefix));
-    let available_ips: Vec<String> = ips
-        .iter()
-        .filter(|ip| !used_ips.contains(ip))
-        .map(|ip| ip.to_string())
-        .collect();
-    let ip_address = available_ips
-        .first()
-        .ok_or_else(|| APIError::bad_request("No available IP addresses"))?;
-    let plan: ServerPlan = {
-        let data: ServerPlansResponse =
-            serde_json::from_str(include_str!("../../data.json")).unwrap();
-        data.plans
+) -> APIResult<(StatusCode, Json<CreateServerResponse>)> {
+    let result: APIResult<CreateServerResponse> = async {
+        token.require_scope(SCOPE_SERVERS_WRITE)?;
+        tracing::debug!(
+            "Creating server with plan {} in region {:?}",
+            payload.plan,
+            payload.region
+        );
+        let owner = match payload.organization_id {
+            Some(organization_id) => {
+                require_org_role(&state, organization_id, token.user_id, ORG_WRITE_ROLES).await?;
+                QuotaOwner::Organization(organization_id)
+            }
+            None => QuotaOwner::User(token.user_id),
+        };
+        let plan = match get_plan(&state.db_pool, payload.plan).await? {
+            Some(plan) if plan.status == PLAN_STATUS_RETIRED => {
+                return Err(APIError::bad_request("Plan is no longer available"));
+            }
+            Some(plan) => plan,
+            None => return Err(APIError::bad_request("Unknown plan")),
+        };
+        let plan_ip_pool_id = plan.ip_pool_id;
+        let plan = ServerPlan::from(plan);
+        let limits = check_quota(&state, owner, &plan.resources).await?;
+        // 同じ鍵が重複して指定されても、一度だけ書き込みます。
+        let ssh_key_ids: Vec<i32> = payload
+            .ssh_key_ids
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>()
             .into_iter()
-            .find(|p| p.id == payload.plan)
-            .unwrap()
-    };
-    let script: Option<String> = if let Some(script_id) = payload.script_id {
-        get_script_by_id(&state.db_pool, script_id).await?
-    } else {
-        None
-    };
-    tracing::debug!("{:?}", script);
-    let server_id = create_domain(CreateDomainRequest {
-        password: payload.server_password.clone(),
-        network: CreateDomainRequestNetwork {
-            address: ip_address.clone(),
-            gateway: env::var("NETWORK_GATEWAY")?,
-            interface: env::var("NETWORK_INTERFACE")?,
-        },
-        resources: CreateDomainRequestResources {
-            cpu: plan.resources.cpu,
-            memory: plan.resources.memory / 1024,
-            disk: format!("{}G", plan.resources.disk),
-        },
-        script,
-    })
-    .await?;
-    add_server(
+            .collect();
+        let authorized_keys =
+            get_public_keys_by_ids(&state.db_pool, token.user_id, &ssh_key_ids).await?;
+        if authorized_keys.len() != ssh_key_ids.len() {
+            return Err(APIError::bad_request("Unknown SSH key"));
+        }
+        let password_authentication =
+            payload.server_password.is_some() && !payload.disable_password_login;
+        if !password_authentication && authorized_keys.is_empty() {
+            return Err(APIError::bad_request(
+                "An SSH key is required when password login is disabled",
+            ));
+        }
+        let script: Option<String> = if let Some(script_id) = payload.script_id {
+            Some(
+                get_script_by_id(&state.db_pool, script_id, token.user_id)
+                    .await?
+                    .ok_or_else(|| APIError::bad_request("Unknown setup script"))?,
+            )
+        } else {
+            None
+        };
+        let addresses =
+            allocate_server_addresses(&state, plan_ip_pool_id, payload.region.as_deref()).await?;
+        let server_id = generate_server_id()?;
+        // パスワードはジョブに保存せず、ワーカーがRedisから読み出します。
+        if let Some(password) = &payload.server_password {
+            store_server_password(&state.redis_pool, &server_id, password).await?;
+        }
+        let request = CreateDomainRequest {
+            password: None,
+            authorized_keys,
+            password_authentication,
+            network: CreateDomainRequestNetwork {
+                address: addresses.address.clone(),
+                gateway: addresses.gateway.clone(),
+                ipv6: addresses
+                    .ipv6_address
+                    .clone()
+                    .zip(addresses.ipv6_gateway.clone())
+                    .map(|(address, gateway)| CreateDomainRequestIpv6 { address, gateway }),
+                interface: addresses.interface.clone(),
+                dns_servers: addresses.dns_servers.clone(),
+                vlan_id: addresses.vlan_id,
+            },
+            resources: CreateDomainRequestResources {
+                cpu: plan.resources.cpu,
+                memory: plan.resources.memory / 1024,
+                disk: format!("{}G", plan.resources.disk),
+            },
+            script,
+        };
+        let server = NewServer {
+            id: server_id.clone(),
+            name: payload.name,
+            addresses: &addresses,
+            plan: payload.plan,
+            author_id: token.user_id,
+            organization_id: payload.organization_id,
+            limits,
+        };
+        let job_payload = ServerJobPayload {
+            request,
+            has_password: payload.server_password.is_some(),
+        };
+        let job_id =
+            match add_server(&state.db_pool, server, serde_json::to_string(&job_payload)?).await {
+                Ok(Some(job_id)) => job_id,
+                result => {
+                    // 登録できなかった場合は、割り当てたアドレスを解放します。
+                    release_ip_allocation(&state.db_pool, &addresses.allocation_ids).await?;
+                    return Err(match result {
+                        Err(e) => e.into(),
+                        _ => APIError::forbidden("Quota exceeded"),
+                    });
+                }
+            };
+        Ok(CreateServerResponse {
+            id: server_id,
+            job_id,
+        })
+    }
+    .await;
+    let target = result
+        .as_ref()
+        .ok()
+        .map(|response| server_target(&response.id));
+    audit_result(
         &state.db_pool,
-        server_id,
-        payload.name,
-        ip_address.to_string(),
-        payload.plan,
-        token.user_id,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_CREATE,
+        target,
+        &result,
     )
-    .await
-    .map_err(|e| APIError::internal_server_error(&e.to_string()))?;
-    Ok(())
+    .await;
+    result.map(|response| (StatusCode::ACCEPTED, Json(response)))
 }
 
 #[derive(Serialize)]
@@ -123,46 +301,74 @@ pub struct GetServerResponse {
     pub name: String,
     pub plan: i32,
     pub ip_address: String,
+    pub ipv6_address: Option<String>,
+    // online、offline、provisioning、failedのいずれかです。
     pub status: String,
 }
 
+// 稼働状態を返します。作成中や作成に失敗したサーバーは、その状態をそのまま返します。
+fn server_status(lifecycle: String, online: bool) -> String {
+    if lifecycle != SERVER_STATUS_RUNNING {
+        lifecycle
+    } else if online {
+        "online".to_string()
+    } else {
+        "offline".to_string()
+    }
+}
+
+// 作成が終わったサーバーの、VMコントローラーでのIDを取得します。
+pub async fn require_domain_id(state: &AppState, server_id: &str) -> APIResult<String> {
+    get_domain_id(&state.db_pool, server_id)
+        .await?
+        .ok_or_else(|| APIError::bad_request("Server is not ready yet"))
+}
+
+// VMコントローラーに問い合わせて、サーバーの一覧に稼働状態を付け加えます。
+pub async fn with_status(
+    pool: &PgPool,
+    servers: Vec<(String, String, i32, String, Option<String>, String)>,
+) -> anyhow::Result<Vec<GetServerResponse>> {
+    let server_ids: Vec<String> = servers.iter().map(|(id, ..)| id.clone()).collect();
+    let domain_ids: HashMap<String, String> = get_domain_ids(pool, &server_ids)
+        .await?
+        .into_iter()
+        .collect();
+    let server_onlines = fetch_all_servers(domain_ids.values().cloned().collect())
+        .await?
+        .domains
+        .unwrap_or_default();
+    // 結合する、server_onlines.domainsにサーバのIDが含まれている場合はオンライン、それ以外はオフライン
+    let server_online_set: HashSet<String> = server_onlines.into_iter().collect();
+    let response = servers
+        .into_iter()
+        .map(
+            |(id, name, plan, ip_address, ipv6_address, lifecycle)| GetServerResponse {
+                status: server_status(
+                    lifecycle,
+                    domain_ids
+                        .get(&id)
+                        .is_some_and(|domain_id| server_online_set.contains(domain_id)),
+                ),
+                id,
+                name,
+                plan,
+                ip_address,
+                ipv6_address,
+            },
+        )
+        .collect();
+    Ok(response)
+}
+
 // ユーザーが所有するサーバーの一覧を取得します。
 pub async fn get_all_servers(
     State(state): State<AppState>,
     token: Token,
 ) -> APIResult<Json<Vec<GetServerResponse>>> {
+    token.require_scope(SCOPE_SERVERS_READ)?;
     let servers = get_all_servers_from_user(&state.db_pool, token.user_id).await?;
-    let server_onlines = {
-        let server_ids: Vec<String> = servers.iter().map(|(id, _, _, _)| id.clone()).collect();
-        fetch_all_servers(server_ids)
-            .await?
-            .domains
-            .unwrap_or_default()
-    };
-    // 結合する、server_onlines.domainsにサーバのIDが含まれている場合はオンライン、それ以外はオフライン
-    let server_online_set: std::collections::HashSet<String> = server_onlines.into_iter().collect();
-    let servers: Vec<_> = servers
-        .into_iter()
-        .map(|(id, name, plan, ip_address)| {
-            let status = if server_online_set.contains(&id) {
-                "online".to_string()
-            } else {
-                "offline".to_string()
-            };
-            (id, name, plan, ip_address, status)
-        })
-        .collect();
-    let response = servers
-        .into_iter()
-        .map(|(id, name, plan, ip_address, status)| GetServerResponse {
-            id,
-            name,
-            plan,
-            ip_address,
-            status,
-        })
-        .collect();
-    Ok(Json(response))
+    Ok(Json(with_status(&state.db_pool, servers).await?))
 }
 
 pub async fn get_server_by_id(
@@ -170,19 +376,21 @@ pub async fn get_server_by_id(
     token: Token,
     Path((server_id,)): Path<(String,)>,
 ) -> APIResult<Json<GetServerResponse>> {
-    let server = db_get_server_by_id(&state.db_pool, server_id, token.user_id).await?;
-    if let Some((id, name, plan, ip_address)) = server {
-        let server_model = fetch_server(id.clone()).await?;
+    token.require_scope(SCOPE_SERVERS_READ)?;
+    let server =
+        db_get_server_by_id(&state.db_pool, server_id, token.user_id, ORG_READ_ROLES).await?;
+    if let Some((id, name, plan, ip_address, ipv6_address, lifecycle)) = server {
+        let online = match get_domain_id(&state.db_pool, &id).await? {
+            Some(domain_id) => fetch_server(domain_id).await?.status == "running",
+            None => false,
+        };
         Ok(Json(GetServerResponse {
+            status: server_status(lifecycle, online),
             id,
             name,
             plan,
             ip_address,
-            status: if server_model.status == "running" {
-                "online".to_string()
-            } else {
-                "offline".to_string()
-            },
+            ipv6_address,
         }))
     } else {
         Err(APIError::not_found("Server not found"))
@@ -192,57 +400,150 @@ pub async fn get_server_by_id(
 pub async fn delete_server(
     State(state): State<AppState>,
     token: Token,
+    client: ClientInfo,
     Path((server_id,)): Path<(String,)>,
 ) -> APIResult<()> {
-    domain::delete_server(server_id.clone()).await?;
-    db_delete_server_by_id(&state.db_pool, server_id, token.user_id).await?;
-    Ok(())
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        token.require_scope(SCOPE_SERVERS_WRITE)?;
+        if db_get_server_by_id(
+            &state.db_pool,
+            server_id.clone(),
+            token.user_id,
+            ORG_WRITE_ROLES,
+        )
+        .await?
+        .is_none()
+        {
+            return Err(APIError::not_found("Server not found"));
+        }
+        // 作成が終わる前に削除した場合は、ワーカーが作成後に削除します。
+        if let Some(domain_id) = get_domain_id(&state.db_pool, &server_id).await? {
+            domain::delete_server(domain_id).await?;
+        }
+        db_delete_server_by_id(&state.db_pool, server_id, token.user_id, ORG_WRITE_ROLES).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_DELETE,
+        target,
+        &result,
+    )
+    .await;
+    result
 }
 
 pub async fn shutdown_server(
     State(state): State<AppState>,
     token: Token,
+    client: ClientInfo,
     Path((server_id,)): Path<(String,)>,
 ) -> APIResult<()> {
-    if db_get_server_by_id(&state.db_pool, server_id.clone(), token.user_id)
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        token.require_scope(SCOPE_SERVERS_WRITE)?;
+        if db_get_server_by_id(
+            &state.db_pool,
+            server_id.clone(),
+            token.user_id,
+            ORG_WRITE_ROLES,
+        )
         .await?
         .is_none()
-    {
-        return Err(APIError::not_found("Server not found"));
-    }
+        {
+            return Err(APIError::not_found("Server not found"));
+        }
 
-    domain::shutdown_server(server_id).await?;
-    Ok(())
+        domain::shutdown_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_SHUTDOWN,
+        target,
+        &result,
+    )
+    .await;
+    result
 }
 
 pub async fn power_on_server(
     State(state): State<AppState>,
     token: Token,
+    client: ClientInfo,
     Path((server_id,)): Path<(String,)>,
 ) -> APIResult<()> {
-    if db_get_server_by_id(&state.db_pool, server_id.clone(), token.user_id)
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        token.require_scope(SCOPE_SERVERS_WRITE)?;
+        if db_get_server_by_id(
+            &state.db_pool,
+            server_id.clone(),
+            token.user_id,
+            ORG_WRITE_ROLES,
+        )
         .await?
         .is_none()
-    {
-        return Err(APIError::not_found("Server not found"));
-    }
+        {
+            return Err(APIError::not_found("Server not found"));
+        }
 
-    domain::power_on_server(server_id).await?;
-    Ok(())
+        domain::power_on_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_POWER_ON,
+        target,
+        &result,
+    )
+    .await;
+    result
 }
 
 pub async fn restart_server(
     State(state): State<AppState>,
     token: Token,
+    client: ClientInfo,
     Path((server_id,)): Path<(String,)>,
 ) -> APIResult<()> {
-    if db_get_server_by_id(&state.db_pool, server_id.clone(), token.user_id)
+    let target = Some(server_target(&server_id));
+    let result: APIResult<()> = async {
+        token.require_scope(SCOPE_SERVERS_WRITE)?;
+        if db_get_server_by_id(
+            &state.db_pool,
+            server_id.clone(),
+            token.user_id,
+            ORG_WRITE_ROLES,
+        )
         .await?
         .is_none()
-    {
-        return Err(APIError::not_found("Server not found"));
-    }
+        {
+            return Err(APIError::not_found("Server not found"));
+        }
 
-    domain::restart_server(server_id).await?;
-    Ok(())
+        domain::restart_server(require_domain_id(&state, &server_id).await?).await?;
+        Ok(())
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        Some(token.user_id),
+        AUDIT_SERVER_RESTART,
+        target,
+        &result,
+    )
+    .await;
+    result
 }
@@ -6,11 +6,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     db::setup_script::{
-        db_create_setup_script, db_get_all_setup_scripts, delete_setup_script, get_scriptdata_by_id, set_setup_script
+        db_create_setup_script, db_get_all_setup_scripts, delete_setup_script,
+        get_scriptdata_by_id, set_setup_script,
     },
-    error::APIResult,
+    error::{APIError, APIResult},
+    routes::organization::require_org_role,
     state::AppState,
-    token::Token,
+    token::{ORG_WRITE_ROLES, SCOPE_SCRIPTS_READ, SCOPE_SCRIPTS_WRITE, Token},
 };
 
 #[derive(Deserialize)]
@@ -18,6 +20,9 @@ pub struct CreateSetupScriptRequest {
     pub title: String,
     pub description: Option<String>,
     pub script: String,
+    // 作成時に指定した場合は、組織のスクリプトとして作成します。更新時は無視します。
+    #[serde(default)]
+    pub organization_id: Option<i32>,
 }
 
 pub async fn create_setup_script(
@@ -25,12 +30,17 @@ pub async fn create_setup_script(
     token: Token,
     Json(payload): Json<CreateSetupScriptRequest>,
 ) -> APIResult<()> {
+    token.require_scope(SCOPE_SCRIPTS_WRITE)?;
+    if let Some(organization_id) = payload.organization_id {
+        require_org_role(&state, organization_id, token.user_id, ORG_WRITE_ROLES).await?;
+    }
     db_create_setup_script(
         &state.db_pool,
         payload.title,
         payload.description,
         payload.script,
         token.user_id,
+        payload.organization_id,
     )
     .await?;
     Ok(())
@@ -42,24 +52,29 @@ pub struct GetSetupScriptResponse {
     pub title: String,
     pub description: Option<String>,
     pub script: String,
-    pub author_id: i32,
+    pub author_id: Option<i32>,
+    pub organization_id: Option<i32>,
 }
 
 pub async fn get_all_setup_scripts(
     State(state): State<AppState>,
-    _token: Token,
+    token: Token,
 ) -> APIResult<Json<Vec<GetSetupScriptResponse>>> {
-    let scripts = db_get_all_setup_scripts(&state.db_pool).await?;
+    token.require_scope(SCOPE_SCRIPTS_READ)?;
+    let scripts = db_get_all_setup_scripts(&state.db_pool, token.user_id).await?;
     Ok(Json(
         scripts
             .iter()
             .map(
-                |(id, title, description, script, author_id)| GetSetupScriptResponse {
-                    id: *id,
-                    title: title.to_string(),
-                    description: description.clone(),
-                    script: script.to_string(),
-                    author_id: *author_id,
+                |(id, title, description, script, author_id, organization_id)| {
+                    GetSetupScriptResponse {
+                        id: *id,
+                        title: title.to_string(),
+                        description: description.clone(),
+                        script: script.to_string(),
+                        author_id: *author_id,
+                        organization_id: *organization_id,
+                    }
                 },
             )
             .collect(),
@@ -68,11 +83,12 @@ pub async fn get_all_setup_scripts(
 
 pub async fn get_script_by_id(
     State(state): State<AppState>,
-    _token: Token,
+    token: Token,
     Path((script_id,)): Path<(i32,)>,
 ) -> APIResult<Json<GetSetupScriptResponse>> {
-    if let Some((title, description, script, author_id)) =
-        get_scriptdata_by_id(&state.db_pool, script_id).await?
+    token.require_scope(SCOPE_SCRIPTS_READ)?;
+    if let Some((title, description, script, author_id, organization_id)) =
+        get_scriptdata_by_id(&state.db_pool, script_id, token.user_id).await?
     {
         Ok(Json(GetSetupScriptResponse {
             id: script_id,
@@ -80,9 +96,10 @@ pub async fn get_script_by_id(
             description,
             script,
             author_id,
+            organization_id,
         }))
     } else {
-        Err(anyhow::anyhow!("Script not found").into())
+        Err(APIError::not_found("Script not found"))
     }
 }
 
@@ -92,10 +109,12 @@ pub async fn put_script_script(
     Path((script_id,)): Path<(i32,)>,
     Json(payload): Json<CreateSetupScriptRequest>,
 ) -> APIResult<()> {
+    token.require_scope(SCOPE_SCRIPTS_WRITE)?;
     set_setup_script(
         &state.db_pool,
         script_id,
         token.user_id,
+        ORG_WRITE_ROLES,
         payload.title,
         payload.description,
         payload.script,
@@ -109,6 +128,7 @@ pub async fn delete_script(
     token: Token,
     Path((script_id,)): Path<(i32,)>,
 ) -> APIResult<()> {
-    delete_setup_script(&state.db_pool, script_id, token.user_id).await?;
+    token.require_scope(SCOPE_SCRIPTS_WRITE)?;
+    delete_setup_script(&state.db_pool, script_id, token.user_id, ORG_WRITE_ROLES).await?;
     Ok(())
-}
\ No newline at end of file
+}
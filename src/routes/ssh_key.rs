@@ -0,0 +1,100 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::ssh_key::{add_ssh_key, delete_ssh_key, get_ssh_keys_by_user},
+    error::{APIError, APIResult},
+    state::AppState,
+    token::Token,
+    utils::ssh_key::parse_ssh_public_key,
+};
+
+#[derive(Deserialize)]
+pub struct CreateSshKeyRequest {
+    // 省略した場合は公開鍵のコメントを名前にします。
+    pub name: Option<String>,
+    pub public_key: String,
+}
+
+#[derive(Serialize)]
+pub struct CreateSshKeyResponse {
+    pub id: i32,
+    pub fingerprint: String,
+}
+
+// SSHの公開鍵を登録します。
+pub async fn create_ssh_key(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<CreateSshKeyRequest>,
+) -> APIResult<Json<CreateSshKeyResponse>> {
+    token.require_session()?;
+    let key = parse_ssh_public_key(&payload.public_key)
+        .ok_or_else(|| APIError::bad_request("Invalid SSH public key"))?;
+    let name = payload
+        .name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or(key.comment)
+        .unwrap_or(key.key_type);
+    let id = add_ssh_key(
+        &state.db_pool,
+        token.user_id,
+        name,
+        key.public_key,
+        key.fingerprint.clone(),
+    )
+    .await?
+    .ok_or_else(|| APIError::bad_request("This SSH key is already registered"))?;
+    Ok(Json(CreateSshKeyResponse {
+        id,
+        fingerprint: key.fingerprint,
+    }))
+}
+
+#[derive(Serialize)]
+pub struct GetSshKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// 登録済みのSSHの公開鍵の一覧を取得します。
+pub async fn get_ssh_keys(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<GetSshKeyResponse>>> {
+    token.require_session()?;
+    let keys = get_ssh_keys_by_user(&state.db_pool, token.user_id)
+        .await?
+        .into_iter()
+        .map(
+            |(id, name, public_key, fingerprint, created_at)| GetSshKeyResponse {
+                id,
+                name,
+                public_key,
+                fingerprint,
+                created_at,
+            },
+        )
+        .collect();
+    Ok(Json(keys))
+}
+
+pub async fn remove_ssh_key(
+    State(state): State<AppState>,
+    token: Token,
+    Path((key_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if !delete_ssh_key(&state.db_pool, key_id, token.user_id).await? {
+        return Err(APIError::not_found("SSH key not found"));
+    }
+    Ok(())
+}
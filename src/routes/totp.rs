@@ -0,0 +1,249 @@
+use axum::{Json, extract::State};
+use bb8_redis::redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client_info::ClientInfo,
+    db::{
+        totp::{disable_totp, enable_totp, get_totp_secret, use_recovery_code},
+        user::get_userdata_by_id,
+    },
+    error::{APIError, APIResult},
+    routes::user::{LOGIN_SCOPE, check_lockout},
+    state::AppState,
+    token::Token,
+    utils::{
+        lockout::{clear_failures, email_subject, record_failure},
+        passcode::{
+            MAX_PASSCODE_ATTEMPTS, generate_secret_token, hash_passcode, record_failed_attempt,
+        },
+        totp::{generate_recovery_codes, generate_totp_secret, get_otpauth_url, verify_totp_code},
+    },
+};
+
+const TOTP_SETUP_TTL: u64 = 600;
+const LOGIN_CHALLENGE_TTL: i64 = 300;
+
+// TOTPのコード、またはリカバリーコードを検証します。
+// 同じTOTPのコードは有効期間内であっても一度しか使えません。
+pub async fn verify_second_factor(
+    state: &AppState,
+    user_id: i32,
+    secret: &str,
+    code: &str,
+) -> anyhow::Result<bool> {
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        if !verify_totp_code(secret, code)? {
+            return Ok(false);
+        }
+        let mut conn = state.redis_pool.get().await?;
+        let first_use: Option<String> = conn
+            .set_options(
+                format!("totp_used:{user_id}:{code}"),
+                1,
+                SetOptions::default()
+                    .conditional_set(ExistenceCheck::NX)
+                    .with_expiration(SetExpiry::EX(90)),
+            )
+            .await?;
+        return Ok(first_use.is_some());
+    }
+    use_recovery_code(&state.db_pool, user_id, hash_passcode(&code.to_lowercase())).await
+}
+
+// パスワード認証に成功したユーザーに対して、二要素認証のチャレンジを発行します。
+pub async fn create_login_challenge(state: &AppState, user_id: i32) -> anyhow::Result<String> {
+    let challenge = generate_secret_token()?;
+    let mut conn = state.redis_pool.get().await?;
+    let _: () = conn
+        .set_ex(
+            format!("login_challenge:{challenge}"),
+            user_id,
+            LOGIN_CHALLENGE_TTL as u64,
+        )
+        .await?;
+    Ok(challenge)
+}
+
+#[derive(Serialize)]
+pub struct StartTotpEnrollmentResponseModel {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+// TOTPの登録を開始します。確認されるまでは有効になりません。
+pub async fn start_totp_enrollment(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<StartTotpEnrollmentResponseModel>> {
+    token.require_session()?;
+    if get_totp_secret(&state.db_pool, token.user_id)
+        .await?
+        .is_some()
+    {
+        return Err(APIError::bad_request("TOTP is already enabled"));
+    }
+    let (_, email) = get_userdata_by_id(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("User not found"))?;
+    let secret = generate_totp_secret();
+    let otpauth_url = get_otpauth_url(&secret, email)?;
+    {
+        let mut conn = state.redis_pool.get().await?;
+        let key = format!("totp_setup:{}", token.user_id);
+        let _: () = conn.set_ex(key, &secret, TOTP_SETUP_TTL).await?;
+    }
+    Ok(Json(StartTotpEnrollmentResponseModel {
+        secret,
+        otpauth_url,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeRequestModel {
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponseModel {
+    pub recovery_codes: Vec<String>,
+}
+
+// 最初のコードを確認してTOTPを有効にし、リカバリーコードを返します。
+pub async fn confirm_totp_enrollment(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<TotpCodeRequestModel>,
+) -> APIResult<Json<RecoveryCodesResponseModel>> {
+    token.require_session()?;
+    let secret: String = {
+        let mut conn = state.redis_pool.get().await?;
+        let value: Option<String> = conn.get(format!("totp_setup:{}", token.user_id)).await?;
+        value.ok_or_else(|| APIError::gone("TOTP enrollment expired"))?
+    };
+    if !verify_totp_code(&secret, &payload.code)? {
+        return Err(APIError::unauthorized("Invalid TOTP code"));
+    }
+    let recovery_codes = generate_recovery_codes();
+    enable_totp(
+        &state.db_pool,
+        token.user_id,
+        secret,
+        recovery_codes
+            .iter()
+            .map(|code| hash_passcode(code))
+            .collect(),
+    )
+    .await?;
+    {
+        let mut conn = state.redis_pool.get().await?;
+        let _: () = conn.del(format!("totp_setup:{}", token.user_id)).await?;
+    }
+    Ok(Json(RecoveryCodesResponseModel { recovery_codes }))
+}
+
+// リカバリーコードを作り直します。古いコードは使えなくなります。
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<TotpCodeRequestModel>,
+) -> APIResult<Json<RecoveryCodesResponseModel>> {
+    token.require_session()?;
+    let secret = get_totp_secret(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::bad_request("TOTP is not enabled"))?;
+    if !verify_second_factor(&state, token.user_id, &secret, &payload.code).await? {
+        return Err(APIError::unauthorized("Invalid TOTP code"));
+    }
+    let recovery_codes = generate_recovery_codes();
+    enable_totp(
+        &state.db_pool,
+        token.user_id,
+        secret,
+        recovery_codes
+            .iter()
+            .map(|code| hash_passcode(code))
+            .collect(),
+    )
+    .await?;
+    Ok(Json(RecoveryCodesResponseModel { recovery_codes }))
+}
+
+// TOTPを無効にします。有効なコードが必要です。
+pub async fn disable_totp_enrollment(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<TotpCodeRequestModel>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let secret = get_totp_secret(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::bad_request("TOTP is not enabled"))?;
+    if !verify_second_factor(&state, token.user_id, &secret, &payload.code).await? {
+        return Err(APIError::unauthorized("Invalid TOTP code"));
+    }
+    disable_totp(&state.db_pool, token.user_id).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct VerifyLoginChallengeRequestModel {
+    pub challenge: String,
+    pub code: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyLoginChallengeResponseModel {
+    pub token: String,
+}
+
+// パスワード認証で得たチャレンジとTOTPのコードを、トークンと交換します。
+pub async fn verify_login_challenge(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<VerifyLoginChallengeRequestModel>,
+) -> APIResult<Json<VerifyLoginChallengeResponseModel>> {
+    let mut conn = state.redis_pool.get().await?;
+    let key = format!("login_challenge:{}", payload.challenge);
+    let attempts_key = format!("login_challenge_attempts:{}", payload.challenge);
+    let user_id: i32 = {
+        let value: Option<i32> = conn.get(&key).await?;
+        value.ok_or_else(|| APIError::gone("Login challenge expired"))?
+    };
+    // チャレンジを取り直して総当たりされないよう、失敗はパスワードと同じくアカウントごとに数えます。
+    let (_, email) = get_userdata_by_id(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| APIError::gone("Login challenge expired"))?;
+    check_lockout(&state, LOGIN_SCOPE, &email, &client).await?;
+    let secret = get_totp_secret(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| APIError::unauthorized("TOTP is not enabled"))?;
+    if !verify_second_factor(&state, user_id, &secret, &payload.code).await? {
+        if let Some(retry_after) =
+            record_failure(&state.redis_pool, LOGIN_SCOPE, &email_subject(&email)).await?
+        {
+            let _: () = conn.del(&[&key, &attempts_key]).await?;
+            return Err(APIError::too_many_requests(
+                "Too many failed attempts",
+                retry_after,
+            ));
+        }
+        let attempts = record_failed_attempt(&mut conn, &attempts_key, LOGIN_CHALLENGE_TTL).await?;
+        if attempts >= MAX_PASSCODE_ATTEMPTS {
+            let _: () = conn.del(&[&key, &attempts_key]).await?;
+            return Err(APIError::forbidden("Too many failed attempts"));
+        }
+        return Err(APIError::unauthorized("Invalid TOTP code"));
+    }
+    // チャレンジは一度しか使えません。
+    let consumed: Option<i32> = conn.get_del(&key).await?;
+    if consumed.is_none() {
+        return Err(APIError::gone("Login challenge expired"));
+    }
+    let _: () = conn.del(&attempts_key).await?;
+    clear_failures(&state.redis_pool, LOGIN_SCOPE, &email_subject(&email)).await?;
+    Ok(Json(VerifyLoginChallengeResponseModel {
+        token: Token::issue(&state, user_id, client).await?,
+    }))
+}
@@ -1,27 +1,59 @@
 use std::env;
 
 use crate::{
+    client_info::ClientInfo,
     db::{
-        token::add_token,
-        user::{add_user, get_userdata_by_id, get_userid_by_email_and_password},
+        invite_code::find_invite_code,
+        organization::count_sole_owned_organizations,
+        server::{db_delete_server_by_id, get_all_servers_from_user, get_domain_id},
+        setup_script::delete_personal_setup_scripts,
+        token::{
+            delete_all_tokens_by_user, delete_other_tokens_by_user, delete_token_by_id,
+            delete_token_by_nonce, get_tokens_by_user, is_recent_token,
+        },
+        totp::get_totp_secret,
+        user::{
+            add_user, delete_user, get_password_hash_by_id, get_profile_by_id,
+            get_user_credentials_by_email, get_user_role, get_userdata_by_id, get_userid_by_email,
+            set_user_email, set_user_password_hash, update_profile,
+        },
     },
     error::{APIError, APIResult},
+    routes::totp::{create_login_challenge, verify_second_factor},
     state::AppState,
-    token::Token,
-    utils::mail::send_passcode,
+    token::{Token, session_idle_timeout},
+    utils::{
+        api::domain,
+        audit::{AUDIT_USER_LOGIN, AUDIT_USER_REGISTER, audit_result, user_target},
+        avatar::avatar_url,
+        lockout::{
+            attempt_subjects, clear_failures, email_subject, get_lockout, ip_subject,
+            record_failure,
+        },
+        mail::{send_lockout_notice, send_passcode, send_password_reset_token},
+        passcode::{
+            MAX_PASSCODE_ATTEMPTS, generate_passcode, generate_secret_token, record_failed_attempt,
+            sign_passcode, verify_passcode,
+        },
+        password::{PasswordVerification, hash_password, verify_dummy_password, verify_password},
+        token_cache::{invalidate_token, invalidate_user_tokens},
+    },
+};
+use axum::{
+    Json,
+    extract::{Path, State},
 };
-use axum::{Json, extract::State};
-use base64::prelude::*;
 use bb8_redis::redis::AsyncCommands;
-use rand::Rng;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 
 #[derive(Deserialize, Serialize)]
 pub struct CreateUserRequestModel {
     pub username: String,
     pub email: String,
-    pub register_passcode: String,
+    pub invite_code: Option<String>,
+    // 招待コードの代わりに、環境変数REGISTER_PASSCODEの共通パスコードでも登録できます。
+    pub register_passcode: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -29,33 +61,58 @@ pub struct CreateUserResponseModel {
     pub token: String,
 }
 
+// 本登録を待っている仮ユーザーです。コードはハッシュ化して保存します。
+#[derive(Deserialize, Serialize)]
+struct PendingUser {
+    username: String,
+    email: String,
+    code_hash: String,
+    #[serde(default)]
+    invite_code_id: Option<i32>,
+}
+
+const PENDING_USER_TTL: i64 = 3600;
+
 // 仮ユーザーを作成します。
 pub async fn create_user(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<CreateUserRequestModel>,
 ) -> APIResult<Json<CreateUserResponseModel>> {
-    if payload.register_passcode != env::var("REGISTER_PASSCODE")? {
-        return Err(APIError::unauthorized("Invalid passcode"));
-    }
-    let code: String = {
-        let mut rng = rand::rng();
-        (0..6)
-            .map(|_| rng.random_range(0..10).to_string())
-            .collect()
+    check_lockout(&state, REGISTER_SCOPE, &payload.email, &client).await?;
+    let invite_code_id = match (&payload.invite_code, &payload.register_passcode) {
+        (Some(invite_code), _) => {
+            find_invite_code(&state.db_pool, invite_code.clone(), payload.email.clone())
+                .await?
+                .map(Some)
+        }
+        (None, Some(passcode))
+            if env::var("REGISTER_PASSCODE").is_ok_and(|expected| &expected == passcode) =>
+        {
+            Some(None)
+        }
+        _ => None,
     };
-    // TODO: Send email with the code
-    send_passcode(code.clone(), payload.email.clone()).await?;
-    tracing::info!("Generated code for user {}: {}", payload.username, code);
-    let token: String = {
-        let mut buf = [0u8; 32];
-        getrandom::fill(&mut buf)?;
-        BASE64_URL_SAFE_NO_PAD.encode(buf)
+    let Some(invite_code_id) = invite_code_id else {
+        return Err(
+            record_attempt_failure(&state, REGISTER_SCOPE, &payload.email, &client, false).await?,
+        );
     };
+    let code = generate_passcode();
+    let token = generate_secret_token()?;
+    let key = format!("create_user:{token}");
+    let code_hash = sign_passcode(&key, &code)?;
+    send_passcode(code, payload.email.clone()).await?;
+    tracing::info!("Sent registration code to user {}", payload.username);
     {
         let mut conn = state.redis_pool.get().await?;
-        let key = format!("create_user:{token}");
-        let value = serde_json::to_string(&payload)?;
-        let _: () = conn.set_ex(key, value, 3600).await?;
+        let value = serde_json::to_string(&PendingUser {
+            username: payload.username,
+            email: payload.email,
+            code_hash,
+            invite_code_id,
+        })?;
+        let _: () = conn.set_ex(key, value, PENDING_USER_TTL as u64).await?;
     }
     Ok(Json(CreateUserResponseModel { token }))
 }
@@ -75,41 +132,58 @@ pub struct RegisterUserResponseModel {
 // ユーザーの本登録です。
 pub async fn register_user(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<RegisterUserRequestModel>,
 ) -> APIResult<Json<RegisterUserResponseModel>> {
-    let mut conn = state.redis_pool.get().await?;
-    let key = format!("create_user:{}", payload.token);
-    let userdata: Option<CreateUserRequestModel> = {
-        let value: Option<String> = conn.get(key).await?;
-        if let Some(value) = &value {
-            Some(serde_json::from_str(value)?)
-        } else {
-            None
-        }
-    };
-    if let Some(userdata) = &userdata {
-        let password_hash = {
-            let mut hasher = Sha256::new();
-            hasher.update(payload.password.into_bytes());
-            let hash = hasher.finalize();
-            BASE64_URL_SAFE_NO_PAD.encode(hash)
+    let result: APIResult<i32> = async {
+        let mut conn = state.redis_pool.get().await?;
+        let key = format!("create_user:{}", payload.token);
+        let attempts_key = format!("create_user_attempts:{}", payload.token);
+        let userdata: PendingUser = {
+            let value: Option<String> = conn.get(&key).await?;
+            match value {
+                Some(value) => serde_json::from_str(&value)?,
+                None => return Err(APIError::gone("Registration code expired")),
+            }
         };
-        let user_id = add_user(
+        if !verify_passcode(&key, &payload.code, &userdata.code_hash)? {
+            let attempts =
+                record_failed_attempt(&mut conn, &attempts_key, PENDING_USER_TTL).await?;
+            if attempts >= MAX_PASSCODE_ATTEMPTS {
+                // 上限に達したら仮登録ごと無効にします。
+                let _: () = conn.del(&[&key, &attempts_key]).await?;
+                return Err(APIError::forbidden("Too many failed attempts"));
+            }
+            return Err(APIError::unauthorized("Invalid registration code"));
+        }
+        let _: () = conn.del(&[&key, &attempts_key]).await?;
+
+        let password_hash = hash_password(payload.password).await?;
+        // 招待コードは本登録が済んだときに使用済みにします。
+        add_user(
             &state.db_pool,
-            userdata.username.clone(),
-            userdata.email.clone(),
-            password_hash,
+            userdata.username,
+            userdata.email,
+            Some(password_hash),
+            userdata.invite_code_id,
         )
-        .await?;
-        let token = Token::new(user_id)?;
-        let nonce = token.get_nonce_as_string();
-        add_token(&state.db_pool, nonce, user_id).await?;
-        Ok(Json(RegisterUserResponseModel {
-            token: token.generate()?,
-        }))
-    } else {
-        Err(APIError::not_found("User data not found"))
+        .await?
+        .ok_or_else(|| APIError::forbidden("Invite code is no longer valid"))
     }
+    .await;
+    let user_id = result.as_ref().ok().copied();
+    audit_result(
+        &state.db_pool,
+        &client,
+        user_id,
+        AUDIT_USER_REGISTER,
+        user_id.map(user_target),
+        &result,
+    )
+    .await;
+    Ok(Json(RegisterUserResponseModel {
+        token: Token::issue(&state, result?, client).await?,
+    }))
 }
 
 #[derive(Deserialize)]
@@ -120,37 +194,158 @@ pub struct IssueUserTokenRequestModel {
 
 #[derive(Serialize)]
 pub struct IssueUserTokenResponseModel {
-    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    // 二要素認証が有効な場合は、トークンの代わりにチャレンジを返します。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<String>,
+}
+
+pub const LOGIN_SCOPE: &str = "login";
+const REGISTER_SCOPE: &str = "register";
+
+// 失敗を記録し、返すべきエラーを作ります。
+// アカウントのメールアドレスがロックされた場合は、本人にメールで知らせます。
+async fn record_attempt_failure(
+    state: &AppState,
+    scope: &str,
+    email: &str,
+    client: &ClientInfo,
+    notify: bool,
+) -> anyhow::Result<APIError> {
+    let mut retry_after = record_failure(&state.redis_pool, scope, &email_subject(email)).await?;
+    if let (Some(duration), true) = (retry_after, notify) {
+        let email = email.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = send_lockout_notice(duration, email).await {
+                tracing::error!("Failed to send lockout notice: {}", e);
+            }
+        });
+    }
+    if let Some(ip_address) = &client.ip_address {
+        retry_after = retry_after
+            .max(record_failure(&state.redis_pool, scope, &ip_subject(ip_address)).await?);
+    }
+    Ok(match (retry_after, scope) {
+        (Some(retry_after), _) => {
+            APIError::too_many_requests("Too many failed attempts", retry_after)
+        }
+        (None, REGISTER_SCOPE) => APIError::unauthorized("Invalid invite code"),
+        (None, _) => APIError::unauthorized("Invalid email or password"),
+    })
+}
+
+pub async fn check_lockout(
+    state: &AppState,
+    scope: &str,
+    email: &str,
+    client: &ClientInfo,
+) -> APIResult<()> {
+    let subjects = attempt_subjects(email, client.ip_address.as_deref());
+    if let Some(retry_after) = get_lockout(&state.redis_pool, scope, &subjects).await? {
+        return Err(APIError::too_many_requests(
+            "Too many failed attempts",
+            retry_after,
+        ));
+    }
+    Ok(())
 }
 
 // ユーザーのトークンを発行します。(ログインで主に利用します。)
 pub async fn issue_user_token(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<IssueUserTokenRequestModel>,
 ) -> APIResult<Json<IssueUserTokenResponseModel>> {
-    let password_hash = {
-        let mut hasher = Sha256::new();
-        hasher.update(payload.password.into_bytes());
-        let hash = hasher.finalize();
-        BASE64_URL_SAFE_NO_PAD.encode(hash)
-    };
-    let user_id = get_userid_by_email_and_password(&state.db_pool, payload.email, password_hash)
+    let mut target_user = None;
+    let result: APIResult<IssueUserTokenResponseModel> = async {
+        check_lockout(&state, LOGIN_SCOPE, &payload.email, &client).await?;
+        let credentials =
+            get_user_credentials_by_email(&state.db_pool, payload.email.clone()).await?;
+        target_user = credentials.as_ref().map(|(user_id, _, _)| *user_id);
+        // 外部のIDプロバイダーで登録したユーザーはパスワードを持ちません。
+        let Some((user_id, Some(password_hash), suspended)) = credentials else {
+            verify_dummy_password(payload.password.clone()).await?;
+            let exists = credentials.is_some();
+            return Err(record_attempt_failure(
+                &state,
+                LOGIN_SCOPE,
+                &payload.email,
+                &client,
+                exists,
+            )
+            .await?);
+        };
+        match verify_password(payload.password.clone(), password_hash).await? {
+            PasswordVerification::Invalid => {
+                return Err(record_attempt_failure(
+                    &state,
+                    LOGIN_SCOPE,
+                    &payload.email,
+                    &client,
+                    true,
+                )
+                .await?);
+            }
+            PasswordVerification::ValidNeedsRehash => {
+                // 古い形式のハッシュはログイン成功時にArgon2idへ移行します。
+                let password_hash = hash_password(payload.password).await?;
+                set_user_password_hash(&state.db_pool, user_id, password_hash).await?;
+            }
+            PasswordVerification::Valid => {}
+        }
+        if suspended {
+            return Err(APIError::forbidden("Account is suspended"));
+        }
+        complete_login(&state, user_id, client.clone()).await
+    }
+    .await;
+    // 認証に失敗した試行は、操作した人を特定できないので対象のアカウントとしてのみ記録します。
+    audit_result(
+        &state.db_pool,
+        &client,
+        target_user.filter(|_| result.is_ok()),
+        AUDIT_USER_LOGIN,
+        target_user.map(user_target),
+        &result,
+    )
+    .await;
+    Ok(Json(result?))
+}
+
+// 一要素目の認証に成功したユーザーに、トークンか二要素認証のチャレンジを返します。
+// 失敗回数は、二要素認証まで済んだときに消します。
+pub async fn complete_login(
+    state: &AppState,
+    user_id: i32,
+    client: ClientInfo,
+) -> APIResult<IssueUserTokenResponseModel> {
+    let (_, email) = get_userdata_by_id(&state.db_pool, user_id)
         .await?
-        .ok_or_else(|| APIError::unauthorized("Invalid email or password"))?;
-    let token = Token::new(user_id)?;
-    let nonce = token.get_nonce_as_string();
-    add_token(&state.db_pool, nonce, user_id).await?;
-    Ok(Json(IssueUserTokenResponseModel {
-        token: token.generate()?,
-    }))
+        .ok_or_else(|| APIError::not_found("User not found"))?;
+    if get_totp_secret(&state.db_pool, user_id).await?.is_some() {
+        check_lockout(state, LOGIN_SCOPE, &email, &client).await?;
+        return Ok(IssueUserTokenResponseModel {
+            token: None,
+            challenge: Some(create_login_challenge(state, user_id).await?),
+        });
+    }
+    clear_failures(&state.redis_pool, LOGIN_SCOPE, &email_subject(&email)).await?;
+    Ok(IssueUserTokenResponseModel {
+        token: Some(Token::issue(state, user_id, client).await?),
+        challenge: None,
+    })
 }
 
 #[derive(Serialize)]
 pub struct GetUserDataResponseModel {
     pub username: String,
+    pub display_name: Option<String>,
     pub email: String,
-    pub avatar_url: String,
+    pub avatar_url: Option<String>,
+    pub use_gravatar: bool,
     pub id: i32,
+    pub role: String,
 }
 
 // ユーザーのデータを取得します。
@@ -158,20 +353,397 @@ pub async fn get_user(
     State(state): State<AppState>,
     token: Token,
 ) -> APIResult<Json<GetUserDataResponseModel>> {
-    if let Some((username, email)) = get_userdata_by_id(&state.db_pool, token.user_id).await? {
-        let avatar_url = {
-            let mut hasher = Sha256::new();
-            hasher.update(email.as_bytes());
-            let hash = hasher.finalize();
-            format!("https://gravatar.com/avatar/{hash:x}")
-        };
-        Ok(Json(GetUserDataResponseModel {
-            username,
-            email,
-            avatar_url,
-            id: token.user_id,
-        }))
-    } else {
-        Err(APIError::not_found("User not found"))
+    let (username, email, display_name, avatar_key, use_gravatar) =
+        get_profile_by_id(&state.db_pool, token.user_id)
+            .await?
+            .ok_or_else(|| APIError::not_found("User not found"))?;
+    let role = get_user_role(&state.db_pool, token.user_id)
+        .await?
+        .unwrap_or_default();
+    Ok(Json(GetUserDataResponseModel {
+        avatar_url: avatar_url(avatar_key.as_deref(), use_gravatar, &email),
+        username,
+        display_name,
+        email,
+        use_gravatar,
+        id: token.user_id,
+        role,
+    }))
+}
+
+const MAX_USERNAME_LENGTH: usize = 50;
+const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+
+#[derive(Deserialize)]
+pub struct UpdateUserRequestModel {
+    pub username: Option<String>,
+    // nullを指定すると表示名を消します。
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub display_name: Option<Option<String>>,
+    pub use_gravatar: Option<bool>,
+}
+
+// 省略されたフィールドとnullを区別するために使います。
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// ユーザー名や表示名を変更します。指定されたフィールドだけを更新します。
+pub async fn update_user(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<UpdateUserRequestModel>,
+) -> APIResult<Json<GetUserDataResponseModel>> {
+    token.require_session()?;
+    let username = payload.username.map(|username| username.trim().to_string());
+    if username.as_ref().is_some_and(|username| {
+        username.is_empty() || username.chars().count() > MAX_USERNAME_LENGTH
+    }) {
+        return Err(APIError::bad_request(&format!(
+            "Username must be between 1 and {MAX_USERNAME_LENGTH} characters"
+        )));
+    }
+    let display_name = payload.display_name.map(|display_name| {
+        display_name
+            .map(|display_name| display_name.trim().to_string())
+            .filter(|display_name| !display_name.is_empty())
+    });
+    if display_name
+        .as_ref()
+        .and_then(Option::as_ref)
+        .is_some_and(|display_name| display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH)
+    {
+        return Err(APIError::bad_request(&format!(
+            "Display name must be at most {MAX_DISPLAY_NAME_LENGTH} characters"
+        )));
+    }
+    if !update_profile(
+        &state.db_pool,
+        token.user_id,
+        username,
+        display_name,
+        payload.use_gravatar,
+    )
+    .await?
+    {
+        return Err(APIError::bad_request("Username is already taken"));
+    }
+    get_user(State(state), token).await
+}
+
+// 現在のセッションを破棄します。
+pub async fn logout(State(state): State<AppState>, token: Token) -> APIResult<()> {
+    token.require_session()?;
+    let nonce = token.get_nonce_as_string();
+    delete_token_by_nonce(&state.db_pool, nonce.clone(), token.user_id).await?;
+    invalidate_token(&state.redis_pool, token.user_id, &nonce).await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct SessionResponseModel {
+    pub id: i32,
+    pub created_at: Option<NaiveDateTime>,
+    pub last_used_at: NaiveDateTime,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
+// 有効なセッションの一覧を取得します。
+pub async fn get_sessions(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<SessionResponseModel>>> {
+    token.require_session()?;
+    let current_nonce = token.get_nonce_as_string();
+    let sessions = get_tokens_by_user(&state.db_pool, token.user_id, session_idle_timeout())
+        .await?
+        .into_iter()
+        .map(
+            |(id, nonce, created_at, last_used_at, ip_address, user_agent)| SessionResponseModel {
+                id,
+                created_at,
+                last_used_at,
+                ip_address,
+                user_agent,
+                current: nonce == current_nonce,
+            },
+        )
+        .collect();
+    Ok(Json(sessions))
+}
+
+// 指定したセッションを破棄します。
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    token: Token,
+    Path((session_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let nonce = delete_token_by_id(&state.db_pool, session_id, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("Session not found"))?;
+    invalidate_token(&state.redis_pool, token.user_id, &nonce).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequestModel {
+    pub email: String,
+}
+
+const PASSWORD_RESET_TTL: u64 = 3600;
+
+// パスワード再設定用のトークンをメールで送信します。
+// 登録済みのメールアドレスか判別できないよう、常に成功を返します。
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetRequestModel>,
+) -> APIResult<()> {
+    let Some(user_id) = get_userid_by_email(&state.db_pool, payload.email.clone()).await? else {
+        return Ok(());
+    };
+    let token = generate_secret_token()?;
+    {
+        let mut conn = state.redis_pool.get().await?;
+        let key = format!("password_reset:{token}");
+        let _: () = conn.set_ex(key, user_id, PASSWORD_RESET_TTL).await?;
+    }
+    // 送信にかかる時間で判別されないよう、メールはバックグラウンドで送ります。
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset_token(token, payload.email).await {
+            tracing::error!("Failed to send password reset email: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmPasswordResetRequestModel {
+    pub token: String,
+    pub password: String,
+}
+
+// トークンを検証してパスワードを再設定し、すべてのセッションを破棄します。
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<ConfirmPasswordResetRequestModel>,
+) -> APIResult<()> {
+    let user_id: Option<i32> = {
+        let mut conn = state.redis_pool.get().await?;
+        let key = format!("password_reset:{}", payload.token);
+        conn.get_del(key).await?
+    };
+    let user_id = user_id.ok_or_else(|| APIError::gone("Password reset token expired"))?;
+    let password_hash = hash_password(payload.password).await?;
+    set_user_password_hash(&state.db_pool, user_id, password_hash).await?;
+    delete_all_tokens_by_user(&state.db_pool, user_id).await?;
+    invalidate_user_tokens(&state.redis_pool, user_id).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequestModel {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+// パスワードを変更し、現在のセッション以外を破棄します。
+pub async fn change_password(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<ChangePasswordRequestModel>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let password_hash = get_password_hash_by_id(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::bad_request("Password is not set"))?;
+    if let PasswordVerification::Invalid =
+        verify_password(payload.current_password, password_hash).await?
+    {
+        return Err(APIError::unauthorized("Invalid password"));
+    }
+    let password_hash = hash_password(payload.new_password).await?;
+    set_user_password_hash(&state.db_pool, token.user_id, password_hash).await?;
+    delete_other_tokens_by_user(&state.db_pool, token.user_id, token.get_nonce_as_string()).await?;
+    invalidate_user_tokens(&state.redis_pool, token.user_id).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteUserRequestModel {
+    pub password: Option<String>,
+    // パスワードのないアカウントは、TOTPのコードでも確認できます。
+    pub code: Option<String>,
+}
+
+// パスワードのないアカウントが、確認なしで削除できるセッションの経過秒数です。
+const RECENT_SESSION_AGE: i64 = 300;
+
+// ユーザーのサーバーをすべて削除してから、アカウントを削除します。
+// サーバーの削除に失敗した場合はアカウントを残し、再試行できるようにします。
+pub async fn delete_account(state: &AppState, user_id: i32) -> APIResult<()> {
+    // オーナーのいない組織が残らないよう、先に譲渡か削除をしてもらいます。
+    if count_sole_owned_organizations(&state.db_pool, user_id).await? > 0 {
+        return Err(APIError::bad_request(
+            "Transfer or delete the organizations this account solely owns first",
+        ));
+    }
+    for (server_id, ..) in get_all_servers_from_user(&state.db_pool, user_id).await? {
+        // コントローラーに残っていないサーバーは、削除済みとして扱います。
+        if let Some(domain_id) = get_domain_id(&state.db_pool, &server_id).await?
+            && let Err(e) = domain::delete_server(domain_id).await
+        {
+            tracing::error!("Failed to delete server {}: {}", server_id, e);
+            return Err(APIError::service_unavailable(
+                "Failed to delete servers. Please try again later",
+            ));
+        }
+        // サーバーの行を消すと、割り当てていたIPアドレスも解放されます。
+        db_delete_server_by_id(&state.db_pool, server_id, user_id, &[]).await?;
+    }
+    // 組織のサーバーとスクリプトは組織に残ります。
+    delete_personal_setup_scripts(&state.db_pool, user_id).await?;
+    delete_all_tokens_by_user(&state.db_pool, user_id).await?;
+    invalidate_user_tokens(&state.redis_pool, user_id).await?;
+    let avatar_key = get_profile_by_id(&state.db_pool, user_id)
+        .await?
+        .and_then(|(_, _, _, avatar_key, _)| avatar_key);
+    if !delete_user(&state.db_pool, user_id).await? {
+        return Err(APIError::not_found("User not found"));
+    }
+    if let Some(avatar_key) = avatar_key {
+        state.avatar_storage.delete(&avatar_key).await?;
+    }
+    Ok(())
+}
+
+// 自分のアカウントを削除します。パスワードの確認が必要です。
+// OIDCで作成したアカウントなどパスワードがない場合は、TOTPのコードか、
+// 直前にログインしたセッションで確認します。
+pub async fn delete_me(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<DeleteUserRequestModel>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if let Some(password_hash) = get_password_hash_by_id(&state.db_pool, token.user_id).await? {
+        let password = payload
+            .password
+            .ok_or_else(|| APIError::bad_request("Password is required"))?;
+        if let PasswordVerification::Invalid = verify_password(password, password_hash).await? {
+            return Err(APIError::unauthorized("Invalid password"));
+        }
+    } else if let Some(code) = &payload.code {
+        let secret = get_totp_secret(&state.db_pool, token.user_id)
+            .await?
+            .ok_or_else(|| APIError::bad_request("Two-factor authentication is not enabled"))?;
+        if !verify_second_factor(&state, token.user_id, &secret, code).await? {
+            return Err(APIError::unauthorized("Invalid code"));
+        }
+    } else if !is_recent_token(
+        &state.db_pool,
+        token.get_nonce_as_string(),
+        token.user_id,
+        RECENT_SESSION_AGE,
+    )
+    .await?
+    {
+        return Err(APIError::unauthorized(
+            "Please log in again before deleting your account",
+        ));
+    }
+    delete_account(&state, token.user_id).await
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequestModel {
+    pub email: String,
+}
+
+// 確認を待っているメールアドレスの変更です。
+#[derive(Deserialize, Serialize)]
+struct PendingEmailChange {
+    email: String,
+    code_hash: String,
+}
+
+const PENDING_EMAIL_CHANGE_TTL: i64 = 3600;
+
+// 新しいメールアドレスに確認コードを送信します。確認されるまでメールアドレスは変更されません。
+pub async fn change_email(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<ChangeEmailRequestModel>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if get_userid_by_email(&state.db_pool, payload.email.clone())
+        .await?
+        .is_some()
+    {
+        return Err(APIError::bad_request("Email is already in use"));
+    }
+    let code = generate_passcode();
+    let key = format!("change_email:{}", token.user_id);
+    let attempts_key = format!("change_email_attempts:{}", token.user_id);
+    let code_hash = sign_passcode(&key, &code)?;
+    send_passcode(code, payload.email.clone()).await?;
+    let mut conn = state.redis_pool.get().await?;
+    let value = serde_json::to_string(&PendingEmailChange {
+        email: payload.email,
+        code_hash,
+    })?;
+    let _: () = conn
+        .set_ex(key, value, PENDING_EMAIL_CHANGE_TTL as u64)
+        .await?;
+    let _: () = conn.del(attempts_key).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmEmailChangeRequestModel {
+    pub code: String,
+}
+
+// 確認コードを検証し、メールアドレスを変更します。
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<ConfirmEmailChangeRequestModel>,
+) -> APIResult<()> {
+    token.require_session()?;
+    let mut conn = state.redis_pool.get().await?;
+    let key = format!("change_email:{}", token.user_id);
+    let attempts_key = format!("change_email_attempts:{}", token.user_id);
+    let pending: PendingEmailChange = {
+        let value: Option<String> = conn.get(&key).await?;
+        match value {
+            Some(value) => serde_json::from_str(&value)?,
+            None => return Err(APIError::gone("Email change code expired")),
+        }
+    };
+    if !verify_passcode(&key, &payload.code, &pending.code_hash)? {
+        let attempts =
+            record_failed_attempt(&mut conn, &attempts_key, PENDING_EMAIL_CHANGE_TTL).await?;
+        if attempts >= MAX_PASSCODE_ATTEMPTS {
+            let _: () = conn.del(&[&key, &attempts_key]).await?;
+            return Err(APIError::forbidden("Too many failed attempts"));
+        }
+        return Err(APIError::unauthorized("Invalid email change code"));
+    }
+    let _: () = conn.del(&[&key, &attempts_key]).await?;
+    // コードの送信後に同じアドレスで登録された場合に備えて、もう一度確認します。
+    if get_userid_by_email(&state.db_pool, pending.email.clone())
+        .await?
+        .is_some()
+    {
+        return Err(APIError::bad_request("Email is already in use"));
     }
+    set_user_email(&state.db_pool, token.user_id, pending.email).await?;
+    Ok(())
 }
@@ -0,0 +1,267 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use bb8_redis::redis::AsyncCommands;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::*;
+
+use crate::{
+    client_info::ClientInfo,
+    db::{
+        user::{get_user_credentials_by_email, get_userdata_by_id, is_user_suspended},
+        webauthn::{
+            add_webauthn_credential, delete_webauthn_credential, get_passkeys_by_user,
+            get_webauthn_credentials_by_user, touch_webauthn_credential,
+        },
+    },
+    error::{APIError, APIResult},
+    routes::user::IssueUserTokenResponseModel,
+    state::AppState,
+    token::Token,
+    utils::{
+        audit::{AUDIT_USER_LOGIN, audit_result, user_target},
+        passcode::generate_secret_token,
+        webauthn::{encode_credential_id, user_handle, webauthn},
+    },
+};
+
+const WEBAUTHN_CEREMONY_TTL: u64 = 300;
+
+async fn load_passkeys(state: &AppState, user_id: i32) -> anyhow::Result<Vec<Passkey>> {
+    get_passkeys_by_user(&state.db_pool, user_id)
+        .await?
+        .iter()
+        .map(|passkey| Ok(serde_json::from_str(passkey)?))
+        .collect()
+}
+
+// パスキーの登録を開始し、ブラウザに渡すオプションを返します。
+pub async fn start_webauthn_registration(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<CreationChallengeResponse>> {
+    token.require_session()?;
+    let (username, email) = get_userdata_by_id(&state.db_pool, token.user_id)
+        .await?
+        .ok_or_else(|| APIError::not_found("User not found"))?;
+    let exclude_credentials = load_passkeys(&state, token.user_id)
+        .await?
+        .iter()
+        .map(|passkey| passkey.cred_id().clone())
+        .collect();
+    let (options, registration) = webauthn()?
+        .start_passkey_registration(
+            user_handle(token.user_id),
+            &email,
+            &username,
+            Some(exclude_credentials),
+        )
+        .map_err(anyhow::Error::from)?;
+    let mut conn = state.redis_pool.get().await?;
+    let _: () = conn
+        .set_ex(
+            format!("webauthn_register:{}", token.user_id),
+            serde_json::to_string(&registration)?,
+            WEBAUTHN_CEREMONY_TTL,
+        )
+        .await?;
+    Ok(Json(options))
+}
+
+#[derive(Deserialize)]
+pub struct FinishWebauthnRegistrationRequestModel {
+    pub name: String,
+    pub credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Serialize)]
+pub struct FinishWebauthnRegistrationResponseModel {
+    pub id: i32,
+}
+
+// 認証器の応答を検証し、パスキーを登録します。
+pub async fn finish_webauthn_registration(
+    State(state): State<AppState>,
+    token: Token,
+    Json(payload): Json<FinishWebauthnRegistrationRequestModel>,
+) -> APIResult<Json<FinishWebauthnRegistrationResponseModel>> {
+    token.require_session()?;
+    let registration: PasskeyRegistration = {
+        let mut conn = state.redis_pool.get().await?;
+        let value: Option<String> = conn
+            .get_del(format!("webauthn_register:{}", token.user_id))
+            .await?;
+        let value = value.ok_or_else(|| APIError::gone("WebAuthn registration expired"))?;
+        serde_json::from_str(&value)?
+    };
+    let passkey = webauthn()?
+        .finish_passkey_registration(&payload.credential, &registration)
+        .map_err(|_| APIError::bad_request("Invalid WebAuthn credential"))?;
+    let id = add_webauthn_credential(
+        &state.db_pool,
+        token.user_id,
+        payload.name,
+        encode_credential_id(passkey.cred_id()),
+        serde_json::to_string(&passkey)?,
+    )
+    .await?;
+    Ok(Json(FinishWebauthnRegistrationResponseModel { id }))
+}
+
+#[derive(Serialize)]
+pub struct GetWebauthnCredentialResponseModel {
+    pub id: i32,
+    pub name: String,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// 登録済みのパスキーの一覧を取得します。
+pub async fn get_webauthn_credentials(
+    State(state): State<AppState>,
+    token: Token,
+) -> APIResult<Json<Vec<GetWebauthnCredentialResponseModel>>> {
+    token.require_session()?;
+    let credentials = get_webauthn_credentials_by_user(&state.db_pool, token.user_id)
+        .await?
+        .into_iter()
+        .map(
+            |(id, name, last_used_at, created_at)| GetWebauthnCredentialResponseModel {
+                id,
+                name,
+                last_used_at,
+                created_at,
+            },
+        )
+        .collect();
+    Ok(Json(credentials))
+}
+
+pub async fn revoke_webauthn_credential(
+    State(state): State<AppState>,
+    token: Token,
+    Path((credential_id,)): Path<(i32,)>,
+) -> APIResult<()> {
+    token.require_session()?;
+    if !delete_webauthn_credential(&state.db_pool, credential_id, token.user_id).await? {
+        return Err(APIError::not_found("Credential not found"));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingWebauthnLogin {
+    user_id: i32,
+    authentication: PasskeyAuthentication,
+}
+
+#[derive(Deserialize)]
+pub struct StartWebauthnLoginRequestModel {
+    pub email: String,
+}
+
+#[derive(Serialize)]
+pub struct StartWebauthnLoginResponseModel {
+    pub challenge: String,
+    pub options: RequestChallengeResponse,
+}
+
+// パスキーでのログインを開始します。
+// アカウントの有無が分からないよう、停止中のアカウントも同じエラーを返します。
+pub async fn start_webauthn_login(
+    State(state): State<AppState>,
+    Json(payload): Json<StartWebauthnLoginRequestModel>,
+) -> APIResult<Json<StartWebauthnLoginResponseModel>> {
+    let (user_id, _, suspended) = get_user_credentials_by_email(&state.db_pool, payload.email)
+        .await?
+        .ok_or_else(|| APIError::unauthorized("No passkey is registered"))?;
+    let passkeys = load_passkeys(&state, user_id).await?;
+    if passkeys.is_empty() || suspended {
+        return Err(APIError::unauthorized("No passkey is registered"));
+    }
+    let (options, authentication) = webauthn()?
+        .start_passkey_authentication(&passkeys)
+        .map_err(anyhow::Error::from)?;
+    let challenge = generate_secret_token()?;
+    let mut conn = state.redis_pool.get().await?;
+    let _: () = conn
+        .set_ex(
+            format!("webauthn_login:{challenge}"),
+            serde_json::to_string(&PendingWebauthnLogin {
+                user_id,
+                authentication,
+            })?,
+            WEBAUTHN_CEREMONY_TTL,
+        )
+        .await?;
+    Ok(Json(StartWebauthnLoginResponseModel { challenge, options }))
+}
+
+#[derive(Deserialize)]
+pub struct FinishWebauthnLoginRequestModel {
+    pub challenge: String,
+    pub credential: PublicKeyCredential,
+}
+
+// 認証器の署名を検証し、トークンを発行します。
+// パスキーはユーザー検証を必須としているため、二要素認証のチャレンジは挟みません。
+pub async fn finish_webauthn_login(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<FinishWebauthnLoginRequestModel>,
+) -> APIResult<Json<IssueUserTokenResponseModel>> {
+    let mut target_user = None;
+    let result: APIResult<IssueUserTokenResponseModel> = async {
+        // チャレンジは一度しか使えません。
+        let pending: PendingWebauthnLogin = {
+            let mut conn = state.redis_pool.get().await?;
+            let value: Option<String> = conn
+                .get_del(format!("webauthn_login:{}", payload.challenge))
+                .await?;
+            let value = value.ok_or_else(|| APIError::gone("WebAuthn challenge expired"))?;
+            serde_json::from_str(&value)?
+        };
+        target_user = Some(pending.user_id);
+        let authentication = webauthn()?
+            .finish_passkey_authentication(&payload.credential, &pending.authentication)
+            .map_err(|_| APIError::unauthorized("Invalid WebAuthn assertion"))?;
+        // 署名カウンターが進んだ場合は保存されているパスキーも更新します。
+        let mut updated = None;
+        for mut passkey in load_passkeys(&state, pending.user_id).await? {
+            if passkey.update_credential(&authentication) == Some(true) {
+                updated = Some(serde_json::to_string(&passkey)?);
+            }
+        }
+        if !touch_webauthn_credential(
+            &state.db_pool,
+            pending.user_id,
+            encode_credential_id(authentication.cred_id()),
+            updated,
+        )
+        .await?
+        {
+            return Err(APIError::unauthorized("Invalid WebAuthn assertion"));
+        }
+        // チャレンジを発行した後に停止された場合に備えて、もう一度確認します。
+        if is_user_suspended(&state.db_pool, pending.user_id).await? {
+            return Err(APIError::forbidden("Account is suspended"));
+        }
+        Ok(IssueUserTokenResponseModel {
+            token: Some(Token::issue(&state, pending.user_id, client.clone()).await?),
+            challenge: None,
+        })
+    }
+    .await;
+    audit_result(
+        &state.db_pool,
+        &client,
+        target_user.filter(|_| result.is_ok()),
+        AUDIT_USER_LOGIN,
+        target_user.map(user_target),
+        &result,
+    )
+    .await;
+    Ok(Json(result?))
+}
@@ -3,10 +3,13 @@ use std::sync::Arc;
 use bb8_redis::{RedisConnectionManager, bb8};
 use sqlx::PgPool;
 
+use crate::utils::avatar::{AvatarStorage, LocalAvatarStorage};
+
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: Arc<PgPool>,
     pub redis_pool: Arc<bb8::Pool<RedisConnectionManager>>,
+    pub avatar_storage: Arc<dyn AvatarStorage>,
 }
 
 impl AppState {
@@ -19,6 +22,7 @@ impl AppState {
         Ok(AppState {
             db_pool: Arc::new(db_pool),
             redis_pool: Arc::new(redis_pool),
+            avatar_storage: Arc::new(LocalAvatarStorage::from_env()),
         })
     }
 }
@@ -1,22 +1,94 @@
+use std::env;
+
 use axum::{RequestPartsExt, extract::FromRequestParts, http::request::Parts};
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
 use base64::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client_info::ClientInfo,
+    db::{
+        api_key::touch_api_key,
+        token::{add_token, delete_expired_tokens, touch_token},
+        user::get_user_role,
+    },
+    error::APIError,
+    state::AppState,
+    utils::{
+        audit::{AUDIT_TOKEN_ISSUE, record_audit_log, user_target},
+        token_cache::{cache_token, is_token_cached},
+    },
+};
+
+// APIキーはセッショントークンと区別するため、この接頭辞を付けて発行します。
+pub const API_KEY_PREFIX: &str = "vpsk_";
+
+pub const SCOPE_SERVERS_READ: &str = "servers:read";
+pub const SCOPE_SERVERS_WRITE: &str = "servers:write";
+pub const SCOPE_SCRIPTS_READ: &str = "scripts:read";
+pub const SCOPE_SCRIPTS_WRITE: &str = "scripts:write";
+
+pub const ALL_SCOPES: &[&str] = &[
+    SCOPE_SERVERS_READ,
+    SCOPE_SERVERS_WRITE,
+    SCOPE_SCRIPTS_READ,
+    SCOPE_SCRIPTS_WRITE,
+];
+
+pub const ROLE_USER: &str = "user";
+pub const ROLE_ADMIN: &str = "admin";
+
+pub const ORG_ROLE_OWNER: &str = "owner";
+pub const ORG_ROLE_ADMIN: &str = "admin";
+pub const ORG_ROLE_MEMBER: &str = "member";
+pub const ORG_ROLE_VIEWER: &str = "viewer";
+
+// 組織のリソースに対して、それぞれの操作を許可するロールです。
+pub const ORG_READ_ROLES: &[&str] = &[
+    ORG_ROLE_OWNER,
+    ORG_ROLE_ADMIN,
+    ORG_ROLE_MEMBER,
+    ORG_ROLE_VIEWER,
+];
+pub const ORG_WRITE_ROLES: &[&str] = &[ORG_ROLE_OWNER, ORG_ROLE_ADMIN, ORG_ROLE_MEMBER];
+pub const ORG_MANAGE_ROLES: &[&str] = &[ORG_ROLE_OWNER, ORG_ROLE_ADMIN];
+
+const DEFAULT_SESSION_TTL: i64 = 60 * 60 * 24 * 30;
+
+// セッションの有効期限(秒)です。
+pub fn session_ttl() -> i64 {
+    env::var("SESSION_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SESSION_TTL)
+}
 
-use crate::{db::token::exist_token, error::APIError, state::AppState};
+// 最後の利用からこの秒数が経過したセッションは無効になります。未設定の場合は無効になりません。
+pub fn session_idle_timeout() -> Option<i64> {
+    env::var("SESSION_IDLE_TIMEOUT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
 
 pub struct Token {
     pub user_id: i32,
     pub nonce: [u8; 32],
+    // APIキーで認証された場合のみ、許可されたスコープが入ります。
+    pub scopes: Option<Vec<String>>,
 }
 
 impl Token {
     pub fn new(user_id: i32) -> anyhow::Result<Self> {
         let mut nonce = [0; 32];
         getrandom::fill(&mut nonce)?;
-        Ok(Self { user_id, nonce })
+        Ok(Self {
+            user_id,
+            nonce,
+            scopes: None,
+        })
     }
 
     pub fn generate(&self) -> anyhow::Result<String> {
@@ -29,17 +101,76 @@ impl Token {
 
     pub fn parse(token: String) -> anyhow::Result<Self> {
         let buffer = BASE64_URL_SAFE_NO_PAD.decode(token.as_bytes())?;
+        if buffer.len() != 37 || buffer[4] != b'.' {
+            anyhow::bail!("Malformed token");
+        }
         let mut user_id_bytes = [0u8; 4];
         user_id_bytes.copy_from_slice(&buffer[..4]);
         let user_id = i32::from_be_bytes(user_id_bytes);
         let mut nonce = [0u8; 32];
         nonce.copy_from_slice(&buffer[5..]);
-        Ok(Self { user_id, nonce })
+        Ok(Self {
+            user_id,
+            nonce,
+            scopes: None,
+        })
     }
 
     pub fn get_nonce_as_string(&self) -> String {
         BASE64_URL_SAFE_NO_PAD.encode(self.nonce)
     }
+
+    // APIキーはnonceをそのまま保存せず、ハッシュで照合します。
+    pub fn get_nonce_hash(&self) -> String {
+        format!("{:x}", Sha256::digest(self.nonce))
+    }
+
+    // セッショントークンであれば、どのスコープも許可されます。
+    pub fn require_scope(&self, scope: &str) -> Result<(), APIError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s == scope) => Err(APIError::forbidden(
+                &format!("API key is missing the {scope} scope"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // アカウントの管理など、APIキーでは行えない操作で使います。
+    pub fn require_session(&self) -> Result<(), APIError> {
+        if self.scopes.is_some() {
+            return Err(APIError::forbidden("This action requires a session token"));
+        }
+        Ok(())
+    }
+
+    // 新しいセッションを作成し、クライアントに渡すトークン文字列を返します。
+    pub async fn issue(
+        state: &AppState,
+        user_id: i32,
+        client: ClientInfo,
+    ) -> anyhow::Result<String> {
+        delete_expired_tokens(&state.db_pool, user_id).await?;
+        let token = Token::new(user_id)?;
+        record_audit_log(
+            &state.db_pool,
+            &client,
+            Some(user_id),
+            AUDIT_TOKEN_ISSUE,
+            Some(user_target(user_id)),
+            None,
+        )
+        .await;
+        add_token(
+            &state.db_pool,
+            token.get_nonce_as_string(),
+            user_id,
+            session_ttl(),
+            client.ip_address,
+            client.user_agent,
+        )
+        .await?;
+        token.generate()
+    }
 }
 
 impl FromRequestParts<AppState> for Token {
@@ -54,14 +185,68 @@ impl FromRequestParts<AppState> for Token {
             .await
             .map_err(|_| APIError::unauthorized("Missing authorization header"))?;
 
-        let token = Token::parse(bearer.token().to_string())?;
+        if let Some(api_key) = bearer.token().strip_prefix(API_KEY_PREFIX) {
+            let mut token = Token::parse(api_key.to_string())
+                .map_err(|_| APIError::unauthorized("Invalid API key"))?;
+            let scopes = touch_api_key(&state.db_pool, token.get_nonce_hash(), token.user_id)
+                .await?
+                .ok_or_else(|| APIError::unauthorized("Invalid API key"))?;
+            token.scopes = Some(scopes);
+            return Ok(token);
+        }
+
+        let token = Token::parse(bearer.token().to_string())
+            .map_err(|_| APIError::unauthorized("Invalid token"))?;
 
         let nonce = token.get_nonce_as_string();
 
-        if !exist_token(&state.db_pool, nonce, token.user_id).await? {
+        // キャッシュに失敗してもPostgresで検証できるため、Redisのエラーは警告に留めます。
+        match is_token_cached(&state.redis_pool, token.user_id, &nonce).await {
+            Ok(true) => return Ok(token),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to read token cache: {}", e),
+        }
+
+        if !touch_token(
+            &state.db_pool,
+            nonce.clone(),
+            token.user_id,
+            session_idle_timeout(),
+        )
+        .await?
+        {
             return Err(APIError::unauthorized("Invalid token"));
         }
 
+        if let Err(e) = cache_token(&state.redis_pool, token.user_id, &nonce).await {
+            tracing::warn!("Failed to write token cache: {}", e);
+        }
+
         Ok(token)
     }
 }
+
+// 管理者のセッションでのみ取り出せるトークンです。
+pub struct AdminToken(pub Token);
+
+impl FromRequestParts<AppState> for AdminToken {
+    type Rejection = APIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let token = Token::from_request_parts(parts, state).await?;
+        token.require_session()?;
+
+        if get_user_role(&state.db_pool, token.user_id)
+            .await?
+            .as_deref()
+            != Some(ROLE_ADMIN)
+        {
+            return Err(APIError::forbidden("Administrator privileges required"));
+        }
+
+        Ok(AdminToken(token))
+    }
+}
@@ -1,23 +1,55 @@
-use std::env;
+use std::{env, fmt, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+// VMコントローラーが混雑していて、依頼を処理せずに断ったことを表します。
+#[derive(Debug)]
+pub struct DomainUnavailable(pub reqwest::StatusCode);
+
+impl fmt::Display for DomainUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Controller is unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for DomainUnavailable {}
+
+// サーバーの作成を待つ時間の上限です。ワーカーのジョブの期限より短くします。
+pub const CREATE_DOMAIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+// ワーカーが後で送るため、ジョブに保存できるようにします。
+#[derive(Serialize, Deserialize)]
 pub struct CreateDomainRequest {
-    pub password: String,
+    pub password: Option<String>,
+    // サーバーのauthorized_keysに書き込む公開鍵です。
+    pub authorized_keys: Vec<String>,
+    // falseの場合、SSHでのパスワードログインを無効にします。
+    pub password_authentication: bool,
     pub network: CreateDomainRequestNetwork,
     pub resources: CreateDomainRequestResources,
     pub script: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct CreateDomainRequestNetwork {
     pub address: String,
     pub gateway: String,
+    // IPv6のネットワークが設定されたプールの場合のみ指定します。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<CreateDomainRequestIpv6>,
     pub interface: String,
+    pub dns_servers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vlan_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateDomainRequestIpv6 {
+    pub address: String,
+    pub gateway: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct CreateDomainRequestResources {
     pub cpu: i32,
     pub memory: i32,
@@ -29,14 +61,28 @@ pub struct CreateDomainResponse {
     pub id: String,
 }
 
-pub async fn create_domain(payload: CreateDomainRequest) -> anyhow::Result<String> {
-    let response = reqwest::Client::new()
+// VMコントローラーが決めたサーバーのIDを返します。
+// 同じ依頼を二度送っても二重に作成されないよう、サーバーのIDを冪等キーとして送ります。
+pub async fn create_domain(
+    server_id: &str,
+    payload: &CreateDomainRequest,
+) -> anyhow::Result<String> {
+    let response = reqwest::Client::builder()
+        .timeout(CREATE_DOMAIN_TIMEOUT)
+        .build()?
         .post(format!("{}/domains", env::var("VM_CONTROLLER_ENDPOINT")?))
-        .json(&payload)
+        .header("Idempotency-Key", server_id)
+        .json(payload)
         .send()
         .await?;
-    if !response.status().is_success() {
-        return anyhow::bail!("Failed to create domain: {}", response.status());
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+    {
+        return Err(DomainUnavailable(status).into());
+    }
+    if !status.is_success() {
+        anyhow::bail!("Failed to create domain: {}", status);
     }
     let response_body: CreateDomainResponse = response.json().await?;
     Ok(response_body.id)
@@ -54,7 +100,7 @@ pub async fn fetch_all_servers(server_ids: Vec<String>) -> anyhow::Result<AddSer
         .send()
         .await?;
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to get all servers: {}", response.status());
+        anyhow::bail!("Failed to get all servers: {}", response.status());
     }
     let text = response.text().await?;
     tracing::debug!("Fetched server online status: {}", text);
@@ -77,7 +123,7 @@ pub async fn fetch_server(server_id: String) -> anyhow::Result<ServerModel> {
         .send()
         .await?;
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to fetch server: {}", response.status());
+        anyhow::bail!("Failed to fetch server: {}", response.status());
     }
     let response_body: ServerModel = response.json().await?;
     Ok(response_body)
@@ -92,8 +138,16 @@ pub async fn delete_server(server_id: String) -> anyhow::Result<()> {
         ))
         .send()
         .await?;
+    // 既に削除されている場合も成功として扱い、途中で失敗した削除や
+    // アプリの外で消されたサーバーがあっても、削除を続けられるようにします。
+    if matches!(
+        response.status(),
+        reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+    ) {
+        return Ok(());
+    }
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to delete server: {}", response.status());
+        anyhow::bail!("Failed to delete server: {}", response.status());
     }
     Ok(())
 }
@@ -108,7 +162,7 @@ pub async fn shutdown_server(server_id: String) -> anyhow::Result<()> {
         .send()
         .await?;
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to shutdown server: {}", response.status());
+        anyhow::bail!("Failed to shutdown server: {}", response.status());
     }
     Ok(())
 }
@@ -123,7 +177,7 @@ pub async fn power_on_server(server_id: String) -> anyhow::Result<()> {
         .send()
         .await?;
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to power on server: {}", response.status());
+        anyhow::bail!("Failed to power on server: {}", response.status());
     }
     Ok(())
 }
@@ -138,7 +192,7 @@ pub async fn restart_server(server_id: String) -> anyhow::Result<()> {
         .send()
         .await?;
     if !response.status().is_success() {
-        return anyhow::bail!("Failed to restart server: {}", response.status());
+        anyhow::bail!("Failed to restart server: {}", response.status());
     }
     Ok(())
 }
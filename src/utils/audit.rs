@@ -0,0 +1,70 @@
+use sqlx::PgPool;
+
+use crate::{
+    client_info::ClientInfo,
+    db::audit_log::{NewAuditLog, add_audit_log},
+    error::APIResult,
+};
+
+// 監査ログに記録する操作の種類です。
+pub const AUDIT_USER_LOGIN: &str = "user.login";
+pub const AUDIT_USER_REGISTER: &str = "user.register";
+pub const AUDIT_TOKEN_ISSUE: &str = "token.issue";
+pub const AUDIT_API_KEY_CREATE: &str = "api_key.create";
+pub const AUDIT_SERVER_CREATE: &str = "server.create";
+pub const AUDIT_SERVER_DELETE: &str = "server.delete";
+pub const AUDIT_SERVER_SHUTDOWN: &str = "server.shutdown";
+pub const AUDIT_SERVER_POWER_ON: &str = "server.power_on";
+pub const AUDIT_SERVER_RESTART: &str = "server.restart";
+
+pub const OUTCOME_SUCCESS: &str = "success";
+pub const OUTCOME_FAILURE: &str = "failure";
+
+pub fn user_target(user_id: i32) -> String {
+    format!("user:{user_id}")
+}
+
+pub fn server_target(server_id: &str) -> String {
+    format!("server:{server_id}")
+}
+
+// 監査ログを記録します。reasonを指定した場合は失敗として記録します。
+// 記録できなくても元の操作は失敗させず、エラーログに残すだけにします。
+pub async fn record_audit_log(
+    pool: &PgPool,
+    client: &ClientInfo,
+    actor_id: Option<i32>,
+    action: &str,
+    target: Option<String>,
+    reason: Option<String>,
+) {
+    let entry = NewAuditLog {
+        actor_id,
+        action,
+        target,
+        outcome: if reason.is_none() {
+            OUTCOME_SUCCESS
+        } else {
+            OUTCOME_FAILURE
+        },
+        reason,
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+    };
+    if let Err(e) = add_audit_log(pool, entry).await {
+        tracing::error!("Failed to write audit log: {}", e);
+    }
+}
+
+// 処理の結果を監査ログに記録します。エラーの場合はそのメッセージを理由にします。
+pub async fn audit_result<T>(
+    pool: &PgPool,
+    client: &ClientInfo,
+    actor_id: Option<i32>,
+    action: &str,
+    target: Option<String>,
+    result: &APIResult<T>,
+) {
+    let reason = result.as_ref().err().map(|e| e.message.clone());
+    record_audit_log(pool, client, actor_id, action, target, reason).await;
+}
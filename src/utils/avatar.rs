@@ -0,0 +1,113 @@
+use std::{env, io::Cursor, path::PathBuf};
+
+use async_trait::async_trait;
+use image::{ImageFormat, ImageReader, Limits, imageops::FilterType};
+use sha2::{Digest, Sha256};
+
+const DEFAULT_AVATAR_MAX_BYTES: usize = 2 * 1024 * 1024;
+// 保存するアバターの一辺のピクセル数です。
+const AVATAR_SIZE: u32 = 256;
+const MAX_SOURCE_DIMENSION: u32 = 4096;
+
+// アバターの保存先です。ローカルディスク以外に保存する場合はこのトレイトを実装します。
+#[async_trait]
+pub trait AvatarStorage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()>;
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+pub struct LocalAvatarStorage {
+    dir: PathBuf,
+}
+
+impl LocalAvatarStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::var("AVATAR_STORAGE_DIR").unwrap_or_else(|_| "./avatars".to_string()))
+    }
+}
+
+#[async_trait]
+impl AvatarStorage for LocalAvatarStorage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.dir.join(key), data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.dir.join(key)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn avatar_max_bytes() -> usize {
+    env::var("AVATAR_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_AVATAR_MAX_BYTES)
+}
+
+// 保存先のキーとして使える文字列かどうかを確認します。パスの区切りなどは受け付けません。
+pub fn is_valid_avatar_key(key: &str) -> bool {
+    key.strip_suffix(".png").is_some_and(|name| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+// アップロードされた画像を検証し、正方形に切り抜いて縮小したPNGを返します。
+// 対応していない形式や壊れた画像の場合はNoneを返します。
+pub async fn process_avatar(data: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+    tokio::task::spawn_blocking(move || {
+        let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        if !matches!(
+            reader.format(),
+            Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP | ImageFormat::Gif)
+        ) {
+            return Ok(None);
+        }
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+        limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+        reader.limits(limits);
+        let Ok(image) = reader.decode() else {
+            return Ok(None);
+        };
+        let mut output = Cursor::new(Vec::new());
+        image
+            .resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, FilterType::Lanczos3)
+            .write_to(&mut output, ImageFormat::Png)?;
+        Ok(Some(output.into_inner()))
+    })
+    .await?
+}
+
+// プロフィールに表示するアバターのURLです。
+// アップロードされたアバターがなく、Gravatarを使う設定の場合のみGravatarのURLを返します。
+pub fn avatar_url(avatar_key: Option<&str>, use_gravatar: bool, email: &str) -> Option<String> {
+    if let Some(avatar_key) = avatar_key {
+        let base_url = env::var("AVATAR_BASE_URL").unwrap_or_default();
+        return Some(format!("{base_url}/avatars/{avatar_key}"));
+    }
+    use_gravatar.then(|| {
+        let hash = Sha256::digest(email.trim().to_lowercase().as_bytes());
+        format!("https://gravatar.com/avatar/{hash:x}")
+    })
+}
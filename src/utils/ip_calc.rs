@@ -1,41 +1,374 @@
-pub fn cidr_to_list(cidr: &str) -> anyhow::Result<(Vec<String>, String)> {
-    let (base_ip, prefix) = cidr
-        .split_once('/')
-        .ok_or(anyhow::anyhow!("Invalid CIDR format"))?;
-    let base_parts: Vec<i32> = base_ip
-        .split('.')
-        .map(|s| s.parse::<i32>())
-        .collect::<Result<Vec<_>, _>>()?;
-    let ip_count = 1 << (32 - prefix.parse::<u8>()?);
-    let mut ips = Vec::with_capacity(ip_count as usize);
-
-    for i in 0..ip_count {
-        let mut ip_parts = base_parts.clone();
-        ip_parts[3] += i;
-
-        for j in (0..4).rev() {
-            if ip_parts[j] > 255 {
-                if j == 0 {
-                    return anyhow::bail!("IP address overflow");
-                }
-                ip_parts[j - 1] += ip_parts[j] / 256;
-                ip_parts[j] %= 256;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+// "アドレス/プレフィックス長"の形式のIPv4ネットワークです。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv4Net {
+    network: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Net {
+    // ホスト部が0でない場合はエラーにします。
+    pub fn new(address: Ipv4Addr, prefix_len: u8) -> anyhow::Result<Self> {
+        if prefix_len > 32 {
+            anyhow::bail!("Invalid prefix length: {prefix_len}");
+        }
+        let net = Self {
+            network: address,
+            prefix_len,
+        };
+        if net.network() != address {
+            anyhow::bail!("Host bits must be zero: {address}/{prefix_len}");
+        }
+        Ok(net)
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) | !self.mask())
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & self.mask() == u32::from(self.network)
+    }
+
+    // サーバーに割り当てられる範囲です。/31と/32以外はネットワークアドレスとブロードキャストアドレスを除きます。
+    fn host_range(&self) -> (u32, u32) {
+        let (first, last) = (u32::from(self.network()), u32::from(self.broadcast()));
+        if self.prefix_len >= 31 {
+            (first, last)
+        } else {
+            (first + 1, last - 1)
+        }
+    }
+
+    pub fn is_host(&self, address: Ipv4Addr) -> bool {
+        let (first, last) = self.host_range();
+        (first..=last).contains(&u32::from(address))
+    }
+}
+
+impl FromStr for Ipv4Net {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = s
+            .split_once('/')
+            .ok_or(anyhow::anyhow!("Invalid CIDR format"))?;
+        Self::new(address.parse()?, prefix_len.parse()?)
+    }
+}
+
+impl fmt::Display for Ipv4Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// "アドレス/プレフィックス長"の形式のIPv6ネットワークです。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ipv6Net {
+    network: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl Ipv6Net {
+    // ホスト部が0でない場合はエラーにします。
+    pub fn new(address: Ipv6Addr, prefix_len: u8) -> anyhow::Result<Self> {
+        if prefix_len > 128 {
+            anyhow::bail!("Invalid prefix length: {prefix_len}");
+        }
+        let net = Self {
+            network: address,
+            prefix_len,
+        };
+        if net.network() != address {
+            anyhow::bail!("Host bits must be zero: {address}/{prefix_len}");
+        }
+        Ok(net)
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX
+            .checked_shl(128 - self.prefix_len as u32)
+            .unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.network) & self.mask())
+    }
+
+    pub fn last(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.network) | !self.mask())
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, address: Ipv6Addr) -> bool {
+        u128::from(address) & self.mask() == u128::from(self.network)
+    }
+
+    // サーバーに割り当てられる範囲です。/127と/128以外はサブネットルーター用のエニーキャストアドレスを除きます。
+    fn host_range(&self) -> (u128, u128) {
+        let (first, last) = (u128::from(self.network()), u128::from(self.last()));
+        if self.prefix_len >= 127 {
+            (first, last)
+        } else {
+            (first + 1, last)
+        }
+    }
+
+    pub fn is_host(&self, address: Ipv6Addr) -> bool {
+        let (first, last) = self.host_range();
+        (first..=last).contains(&u128::from(address))
+    }
+}
+
+impl FromStr for Ipv6Net {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = s
+            .split_once('/')
+            .ok_or(anyhow::anyhow!("Invalid CIDR format"))?;
+        Self::new(address.parse()?, prefix_len.parse()?)
+    }
+}
+
+impl fmt::Display for Ipv6Net {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+// IPv4とIPv6のどちらかのネットワークです。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpNet {
+    V4(Ipv4Net),
+    V6(Ipv6Net),
+}
+
+impl IpNet {
+    pub fn prefix_len(&self) -> u8 {
+        match self {
+            IpNet::V4(net) => net.prefix_len(),
+            IpNet::V6(net) => net.prefix_len(),
+        }
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self, address) {
+            (IpNet::V4(net), IpAddr::V4(address)) => net.contains(address),
+            (IpNet::V6(net), IpAddr::V6(address)) => net.contains(address),
+            _ => false,
+        }
+    }
+
+    pub fn is_host(&self, address: IpAddr) -> bool {
+        match (self, address) {
+            (IpNet::V4(net), IpAddr::V4(address)) => net.is_host(address),
+            (IpNet::V6(net), IpAddr::V6(address)) => net.is_host(address),
+            _ => false,
+        }
+    }
+
+    // startからendまでのうち、サーバーに割り当てられるアドレスを返します。
+    // アドレスを一つずつ計算して返すため、/64などの大きなネットワークでもメモリを使いません。
+    pub fn hosts_in(&self, start: u128, end: u128) -> impl Iterator<Item = IpAddr> + use<> {
+        let net = *self;
+        let (first, last) = self.host_range();
+        (start.max(first)..=end.min(last)).map(move |value| net.address_from_u128(value))
+    }
+
+    // 割り当てられるアドレスの最初と最後を、数値にして返します。
+    pub fn host_range(&self) -> (u128, u128) {
+        match self {
+            IpNet::V4(net) => {
+                let (first, last) = net.host_range();
+                (first as u128, last as u128)
             }
+            IpNet::V6(net) => net.host_range(),
+        }
+    }
+
+    // address_to_u128で数値にしたアドレスを、このネットワークのアドレスファミリーに戻します。
+    pub fn address_from_u128(&self, value: u128) -> IpAddr {
+        match self {
+            IpNet::V4(_) => IpAddr::V4(Ipv4Addr::from(value as u32)),
+            IpNet::V6(_) => IpAddr::V6(Ipv6Addr::from(value)),
         }
-        ips.push(
-            ip_parts
-                .iter()
-                .map(|part| part.to_string())
-                .collect::<Vec<_>>()
-                .join(".")
-                + "/"
-                + prefix,
+    }
+
+    // ゲートウェイがネットワークのホストとして使えるアドレスかどうかを確認します。
+    pub fn validate_gateway(&self, gateway: IpAddr) -> anyhow::Result<()> {
+        if !self.is_host(gateway) {
+            anyhow::bail!("Gateway {gateway} is not a host address of {self}");
+        }
+        Ok(())
+    }
+
+    // サーバーに渡す"アドレス/プレフィックス長"の形式にします。
+    pub fn with_prefix(&self, address: IpAddr) -> String {
+        format!("{address}/{}", self.prefix_len())
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            Ok(IpNet::V6(s.parse()?))
+        } else {
+            Ok(IpNet::V4(s.parse()?))
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpNet::V4(net) => net.fmt(f),
+            IpNet::V6(net) => net.fmt(f),
+        }
+    }
+}
+
+// IPv4とIPv6のアドレスを、同じ大きさの数値として扱います。
+pub fn address_to_u128(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(address) => u32::from(address) as u128,
+        IpAddr::V6(address) => u128::from(address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_range(cidr: &str) -> (IpAddr, IpAddr) {
+        let net: IpNet = cidr.parse().unwrap();
+        let (first, last) = net.host_range();
+        (net.address_from_u128(first), net.address_from_u128(last))
+    }
+
+    fn addr(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_host_range_excludes_network_and_broadcast() {
+        assert_eq!(
+            host_range("192.168.0.0/24"),
+            (addr("192.168.0.1"), addr("192.168.0.254"))
+        );
+        assert_eq!(
+            host_range("0.0.0.0/0"),
+            (addr("0.0.0.1"), addr("255.255.255.254"))
+        );
+    }
+
+    #[test]
+    fn ipv4_host_range_keeps_all_addresses_of_point_to_point_prefixes() {
+        assert_eq!(
+            host_range("192.168.0.0/31"),
+            (addr("192.168.0.0"), addr("192.168.0.1"))
+        );
+        assert_eq!(
+            host_range("192.168.0.7/32"),
+            (addr("192.168.0.7"), addr("192.168.0.7"))
+        );
+    }
+
+    #[test]
+    fn ipv6_host_range_excludes_subnet_router_anycast() {
+        assert_eq!(
+            host_range("2001:db8::/64"),
+            (addr("2001:db8::1"), addr("2001:db8::ffff:ffff:ffff:ffff"))
+        );
+        assert_eq!(
+            host_range("::/0"),
+            (addr("::1"), addr("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"))
+        );
+        assert_eq!(
+            host_range("2001:db8::/127"),
+            (addr("2001:db8::"), addr("2001:db8::1"))
+        );
+        assert_eq!(
+            host_range("2001:db8::5/128"),
+            (addr("2001:db8::5"), addr("2001:db8::5"))
         );
     }
-    // remove first and last IPs if they are network and broadcast addresses
-    if ips.len() > 2 {
-        ips.remove(0);
-        ips.pop();
+
+    #[test]
+    fn rejects_host_bits_and_invalid_prefixes() {
+        assert!("10.0.0.1/24".parse::<Ipv4Net>().is_err());
+        assert!("10.0.0.0/33".parse::<Ipv4Net>().is_err());
+        assert!("10.0.0.0".parse::<Ipv4Net>().is_err());
+        assert!("2001:db8::1/64".parse::<Ipv6Net>().is_err());
+        assert!("2001:db8::/129".parse::<Ipv6Net>().is_err());
+    }
+
+    #[test]
+    fn contains_checks_prefix_and_family() {
+        let net: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(addr("10.255.0.1")));
+        assert!(!net.contains(addr("11.0.0.1")));
+        assert!(!net.contains(addr("::a00:1")));
+        let net: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(addr("2001:db8:ffff::1")));
+        assert!(!net.contains(addr("2001:db9::1")));
+    }
+
+    #[test]
+    fn validate_gateway_requires_a_host_address() {
+        let net: IpNet = "10.0.0.0/24".parse().unwrap();
+        assert!(net.validate_gateway(addr("10.0.0.1")).is_ok());
+        assert!(net.validate_gateway(addr("10.0.0.0")).is_err());
+        assert!(net.validate_gateway(addr("10.0.0.255")).is_err());
+        assert!(net.validate_gateway(addr("10.0.1.1")).is_err());
+    }
+
+    #[test]
+    fn hosts_in_is_lazy_and_clamped_to_host_range() {
+        let net: IpNet = "2001:db8::/64".parse().unwrap();
+        let hosts: Vec<IpAddr> = net.hosts_in(0, u128::MAX).take(2).collect();
+        assert_eq!(hosts, vec![addr("2001:db8::1"), addr("2001:db8::2")]);
+        let net: IpNet = "10.0.0.0/30".parse().unwrap();
+        let hosts: Vec<IpAddr> = net.hosts_in(0, u128::MAX).collect();
+        assert_eq!(hosts, vec![addr("10.0.0.1"), addr("10.0.0.2")]);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for cidr in ["10.0.0.0/24", "2001:db8::/64"] {
+            assert_eq!(cidr.parse::<IpNet>().unwrap().to_string(), cidr);
+        }
+        assert_eq!(
+            "10.0.0.0/24"
+                .parse::<IpNet>()
+                .unwrap()
+                .with_prefix(addr("10.0.0.5")),
+            "10.0.0.5/24"
+        );
     }
-    Ok((ips, prefix.to_string()))
 }
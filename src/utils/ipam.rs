@@ -0,0 +1,273 @@
+use std::{collections::HashSet, env, net::IpAddr};
+
+use crate::{
+    db::ipam::IpPoolSpec,
+    utils::ip_calc::{IpNet, address_to_u128},
+};
+
+// 既定のIPプールの名前です。起動時に環境変数から登録します。
+pub const DEFAULT_IP_POOL: &str = "default";
+
+// NETWORK_CIDR6とNETWORK_GATEWAY6を指定した場合は、IPv6のアドレスも割り当てます。
+// NETWORK_DNS_SERVERSはカンマ区切りで指定します。
+pub fn default_ip_pool_from_env() -> anyhow::Result<IpPoolSpec> {
+    let (cidr, gateway) = (env::var("NETWORK_CIDR")?, env::var("NETWORK_GATEWAY")?);
+    IpPoolNetwork::parse(&cidr, &gateway)?;
+    let ipv6 = match (env::var("NETWORK_CIDR6"), env::var("NETWORK_GATEWAY6")) {
+        (Ok(cidr6), Ok(gateway6)) => {
+            IpPoolNetwork::parse(&cidr6, &gateway6)?;
+            Some((cidr6, gateway6))
+        }
+        _ => None,
+    };
+    let (cidr6, gateway6) = ipv6.unzip();
+    Ok(IpPoolSpec {
+        name: DEFAULT_IP_POOL.to_string(),
+        cidr,
+        gateway,
+        cidr6,
+        gateway6,
+        interface: env::var("NETWORK_INTERFACE")?,
+        dns_servers: parse_dns_servers(&env::var("NETWORK_DNS_SERVERS").unwrap_or_default())?,
+        vlan_id: None,
+        region: None,
+    })
+}
+
+// カンマ区切りのDNSサーバーのアドレスを検証します。
+pub fn parse_dns_servers(value: &str) -> anyhow::Result<Vec<String>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(|server| Ok(server.parse::<IpAddr>()?.to_string()))
+        .collect()
+}
+
+pub struct IpReservationRange {
+    pub start: IpAddr,
+    pub end: IpAddr,
+}
+
+impl IpReservationRange {
+    // 始まりと終わりが同じアドレスファミリーで、始まりの方が小さい場合のみ受け付けます。
+    pub fn parse(start: &str, end: &str) -> Option<Self> {
+        let start: IpAddr = start.parse().ok()?;
+        let end: IpAddr = end.parse().ok()?;
+        (start.is_ipv4() == end.is_ipv4() && start <= end).then_some(Self { start, end })
+    }
+}
+
+// "アドレス/プレフィックス長"の形式からアドレスを取り出します。
+pub fn host_address(address: &str) -> Option<IpAddr> {
+    address.split('/').next()?.parse().ok()
+}
+
+pub struct IpUsage {
+    // ゲートウェイを除いた、サーバーに割り当てられるアドレスの数です。
+    pub total: u128,
+    pub allocated: u128,
+    // 予約範囲に含まれ、割り当てられていないアドレスの数です。
+    pub reserved: u128,
+    pub available: u128,
+}
+
+// プールの片方のアドレスファミリーのネットワークです。
+pub struct IpPoolNetwork {
+    pub net: IpNet,
+    pub gateway: IpAddr,
+}
+
+impl IpPoolNetwork {
+    // ゲートウェイがネットワークに含まれていない場合はエラーにします。
+    pub fn parse(cidr: &str, gateway: &str) -> anyhow::Result<Self> {
+        let net: IpNet = cidr.parse()?;
+        let gateway: IpAddr = gateway.parse()?;
+        net.validate_gateway(gateway)?;
+        Ok(Self { net, gateway })
+    }
+
+    fn is_usable(&self, address: IpAddr) -> bool {
+        address != self.gateway && self.net.is_host(address)
+    }
+
+    // ネットワーク内の予約範囲を、重なりと隣接をまとめて数値の範囲にします。
+    fn merged_reservations(&self, reservations: &[IpReservationRange]) -> Vec<(u128, u128)> {
+        let (first, last) = self.net.host_range();
+        let mut ranges: Vec<(u128, u128)> = reservations
+            .iter()
+            .filter(|range| self.net.contains(range.start) && self.net.contains(range.end))
+            .map(|range| {
+                (
+                    address_to_u128(range.start).max(first),
+                    address_to_u128(range.end).min(last),
+                )
+            })
+            .filter(|(start, end)| start <= end)
+            .collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(u128, u128)> = Vec::new();
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                    *last_end = (*last_end).max(end);
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    // 割り当ても予約もされていない最初のアドレスを探します。
+    // 予約範囲の間だけを順に調べるため、/64の大半を予約していても時間はかかりません。
+    pub fn find_free(
+        &self,
+        allocated: &HashSet<IpAddr>,
+        reservations: &[IpReservationRange],
+    ) -> Option<IpAddr> {
+        let (first, last) = self.net.host_range();
+        let mut gaps = Vec::new();
+        let mut next = Some(first);
+        for (start, end) in self.merged_reservations(reservations) {
+            if let Some(gap_start) = next
+                && gap_start < start
+            {
+                gaps.push((gap_start, start - 1));
+            }
+            next = end.checked_add(1);
+        }
+        if let Some(gap_start) = next {
+            gaps.push((gap_start, last));
+        }
+        gaps.into_iter().find_map(|(start, end)| {
+            self.net
+                .hosts_in(start, end)
+                .find(|address| *address != self.gateway && !allocated.contains(address))
+        })
+    }
+
+    // アドレスを列挙せずに、範囲の大きさから使用状況を計算します。
+    pub fn usage(
+        &self,
+        allocated: &HashSet<IpAddr>,
+        reservations: &[IpReservationRange],
+    ) -> IpUsage {
+        let (first, last) = self.net.host_range();
+        let total = last - first;
+        let allocated: Vec<IpAddr> = allocated
+            .iter()
+            .copied()
+            .filter(|address| self.is_usable(*address))
+            .collect();
+
+        let merged = self.merged_reservations(reservations);
+        let in_reservation = |address: IpAddr| {
+            let value = address_to_u128(address);
+            merged
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&value))
+        };
+        let mut reserved: u128 = merged.iter().map(|(start, end)| end - start + 1).sum();
+        if in_reservation(self.gateway) {
+            reserved -= 1;
+        }
+        reserved -= allocated
+            .iter()
+            .filter(|address| in_reservation(**address))
+            .count() as u128;
+
+        let allocated = allocated.len() as u128;
+        IpUsage {
+            total,
+            allocated,
+            reserved,
+            available: total.saturating_sub(allocated + reserved),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(addresses: &[&str]) -> HashSet<IpAddr> {
+        addresses.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn range(start: &str, end: &str) -> IpReservationRange {
+        IpReservationRange::parse(start, end).unwrap()
+    }
+
+    #[test]
+    fn reservation_range_rejects_mixed_families_and_reversed_ranges() {
+        assert!(IpReservationRange::parse("10.0.0.1", "2001:db8::1").is_none());
+        assert!(IpReservationRange::parse("10.0.0.9", "10.0.0.1").is_none());
+        assert!(IpReservationRange::parse("10.0.0.1", "10.0.0.1").is_some());
+    }
+
+    #[test]
+    fn pool_network_rejects_gateway_outside_network() {
+        assert!(IpPoolNetwork::parse("10.0.0.0/24", "10.0.1.1").is_err());
+        assert!(IpPoolNetwork::parse("10.0.0.0/24", "10.0.0.0").is_err());
+        assert!(IpPoolNetwork::parse("10.0.0.0/24", "2001:db8::1").is_err());
+    }
+
+    #[test]
+    fn find_free_skips_gateway_allocations_and_reservations() {
+        let network = IpPoolNetwork::parse("10.0.0.0/24", "10.0.0.1").unwrap();
+        let allocated = addresses(&["10.0.0.2", "10.0.0.3", "10.0.0.21"]);
+        let reservations = [range("10.0.0.4", "10.0.0.20")];
+        assert_eq!(
+            network.find_free(&allocated, &reservations),
+            Some("10.0.0.22".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn find_free_returns_none_when_pool_is_full() {
+        let network = IpPoolNetwork::parse("10.0.0.0/30", "10.0.0.1").unwrap();
+        assert_eq!(network.find_free(&addresses(&["10.0.0.2"]), &[]), None);
+        let network = IpPoolNetwork::parse("10.0.0.0/24", "10.0.0.1").unwrap();
+        assert_eq!(
+            network.find_free(&HashSet::new(), &[range("10.0.0.0", "10.0.0.255")]),
+            None
+        );
+    }
+
+    #[test]
+    fn find_free_jumps_over_large_ipv6_reservations() {
+        let network = IpPoolNetwork::parse("2001:db8::/64", "2001:db8::1").unwrap();
+        let reservations = [range("2001:db8::1", "2001:db8::ffff:ffff:ffff")];
+        assert_eq!(
+            network.find_free(&addresses(&["2001:db8::1:0:0:0"]), &reservations),
+            Some("2001:db8::1:0:0:1".parse().unwrap())
+        );
+        let reservations = [range("2001:db8::1", "2001:db8::ffff:ffff:ffff:ffff")];
+        assert_eq!(network.find_free(&HashSet::new(), &reservations), None);
+    }
+
+    #[test]
+    fn usage_counts_reserved_addresses_without_allocations_and_gateway() {
+        let network = IpPoolNetwork::parse("10.0.0.0/24", "10.0.0.1").unwrap();
+        let allocated = addresses(&["10.0.0.15", "10.0.0.100"]);
+        let reservations = [
+            range("10.0.0.1", "10.0.0.10"),
+            range("10.0.0.5", "10.0.0.20"),
+        ];
+        let usage = network.usage(&allocated, &reservations);
+        assert_eq!(usage.total, 253);
+        assert_eq!(usage.allocated, 2);
+        assert_eq!(usage.reserved, 18);
+        assert_eq!(usage.available, 233);
+    }
+
+    #[test]
+    fn usage_handles_a_whole_ipv6_64() {
+        let network = IpPoolNetwork::parse("2001:db8::/64", "2001:db8::1").unwrap();
+        let usage = network.usage(&addresses(&["2001:db8::2"]), &[]);
+        assert_eq!(usage.total, (1 << 64) - 2);
+        assert_eq!(usage.allocated, 1);
+        assert_eq!(usage.reserved, 0);
+        assert_eq!(usage.available, (1 << 64) - 3);
+    }
+}
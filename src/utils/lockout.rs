@@ -0,0 +1,91 @@
+use std::env;
+
+use bb8_redis::{RedisConnectionManager, bb8, redis::AsyncCommands};
+
+const DEFAULT_MAX_FAILURES: i64 = 5;
+const DEFAULT_LOCKOUT_SECONDS: u64 = 60;
+const DEFAULT_LOCKOUT_MAX: u64 = 3600;
+// 失敗回数を覚えておく秒数です。最後の失敗から数えます。
+const FAILURE_WINDOW: i64 = 86400;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn failures_key(scope: &str, subject: &str) -> String {
+    format!("failures:{scope}:{subject}")
+}
+
+fn lockout_key(scope: &str, subject: &str) -> String {
+    format!("lockout:{scope}:{subject}")
+}
+
+// 失敗回数は、メールアドレスとIPアドレスのそれぞれについて数えます。
+pub fn email_subject(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+pub fn ip_subject(ip_address: &str) -> String {
+    format!("ip:{ip_address}")
+}
+
+pub fn attempt_subjects(email: &str, ip_address: Option<&str>) -> Vec<String> {
+    let mut subjects = vec![email_subject(email)];
+    subjects.extend(ip_address.map(ip_subject));
+    subjects
+}
+
+// いずれかの対象がロックされていれば、解除までの秒数を返します。
+pub async fn get_lockout(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    scope: &str,
+    subjects: &[String],
+) -> anyhow::Result<Option<u64>> {
+    let mut conn = pool.get().await?;
+    let mut retry_after = None;
+    for subject in subjects {
+        let ttl: i64 = conn.ttl(lockout_key(scope, subject)).await?;
+        if ttl > 0 {
+            retry_after = retry_after.max(Some(ttl as u64));
+        }
+    }
+    Ok(retry_after)
+}
+
+// 失敗を記録し、上限に達してロックされた場合はその秒数を返します。
+// 上限を超えて失敗するたびに、ロックの時間は倍になります。
+pub async fn record_failure(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    scope: &str,
+    subject: &str,
+) -> anyhow::Result<Option<u64>> {
+    let mut conn = pool.get().await?;
+    let key = failures_key(scope, subject);
+    let failures: i64 = conn.incr(&key, 1).await?;
+    let _: () = conn.expire(&key, FAILURE_WINDOW).await?;
+    let max_failures = env_or("LOGIN_MAX_FAILURES", DEFAULT_MAX_FAILURES);
+    if failures < max_failures {
+        return Ok(None);
+    }
+    let exponent = (failures - max_failures).min(16) as u32;
+    let duration = env_or("LOGIN_LOCKOUT_SECONDS", DEFAULT_LOCKOUT_SECONDS)
+        .saturating_mul(1 << exponent)
+        .min(env_or("LOGIN_LOCKOUT_MAX", DEFAULT_LOCKOUT_MAX));
+    let _: () = conn
+        .set_ex(lockout_key(scope, subject), 1, duration)
+        .await?;
+    Ok(Some(duration))
+}
+
+pub async fn clear_failures(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    scope: &str,
+    subject: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let _: () = conn.del(failures_key(scope, subject)).await?;
+    Ok(())
+}
@@ -5,14 +5,13 @@ use lettre::{
     transport::smtp::authentication::Credentials,
 };
 
-pub async fn send_passcode(code: String, mail_to: String) -> anyhow::Result<()> {
-    tracing::debug!("Sending passcode email to {}", mail_to);
+async fn send_mail(subject: &str, body: String, mail_to: String) -> anyhow::Result<()> {
     let msg = Message::builder()
         .from(env::var("MAIL_FROM")?.parse()?)
         .to(mail_to.parse()?)
-        .subject("あなたの登録コード")
+        .subject(subject)
         .header(ContentType::TEXT_PLAIN)
-        .body(format!("あなたの登録コードは：{}", code))?;
+        .body(body)?;
 
     tracing::debug!(
         "Email message created: {:?}",
@@ -27,3 +26,56 @@ pub async fn send_passcode(code: String, mail_to: String) -> anyhow::Result<()>
     mailer.send(msg).await?;
     Ok(())
 }
+
+pub async fn send_passcode(code: String, mail_to: String) -> anyhow::Result<()> {
+    tracing::debug!("Sending passcode email to {}", mail_to);
+    send_mail(
+        "あなたの登録コード",
+        format!("あなたの登録コードは：{}", code),
+        mail_to,
+    )
+    .await
+}
+
+pub async fn send_password_reset_token(token: String, mail_to: String) -> anyhow::Result<()> {
+    tracing::debug!("Sending password reset email to {}", mail_to);
+    send_mail(
+        "パスワードの再設定",
+        format!(
+            "パスワード再設定用のトークンは：{}\n有効期限は1時間です。心当たりがない場合はこのメールを破棄してください。",
+            token
+        ),
+        mail_to,
+    )
+    .await
+}
+
+pub async fn send_lockout_notice(retry_after: u64, mail_to: String) -> anyhow::Result<()> {
+    tracing::debug!("Sending lockout notice to {}", mail_to);
+    send_mail(
+        "アカウントが一時的にロックされました",
+        format!(
+            "ログインの失敗が続いたため、あなたのアカウントは{}秒間ロックされました。\n心当たりがない場合は、パスワードの変更をおすすめします。",
+            retry_after
+        ),
+        mail_to,
+    )
+    .await
+}
+
+pub async fn send_organization_invite(
+    organization_name: String,
+    token: String,
+    mail_to: String,
+) -> anyhow::Result<()> {
+    tracing::debug!("Sending organization invite to {}", mail_to);
+    send_mail(
+        "組織への招待",
+        format!(
+            "組織「{}」に招待されました。\n招待を受け入れるためのトークンは：{}\n有効期限は7日間です。",
+            organization_name, token
+        ),
+        mail_to,
+    )
+    .await
+}
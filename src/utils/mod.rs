@@ -1,3 +1,15 @@
 pub mod api;
+pub mod audit;
+pub mod avatar;
 pub mod ip_calc;
+pub mod ipam;
+pub mod lockout;
 pub mod mail;
+pub mod oidc;
+pub mod passcode;
+pub mod password;
+pub mod provisioning;
+pub mod ssh_key;
+pub mod token_cache;
+pub mod totp;
+pub mod webauthn;
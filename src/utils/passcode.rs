@@ -0,0 +1,81 @@
+use std::env;
+
+use base64::prelude::*;
+use bb8_redis::redis::{AsyncCommands, aio::MultiplexedConnection};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+// コードの入力に失敗できる回数の上限です。
+pub const MAX_PASSCODE_ATTEMPTS: i64 = 5;
+
+// メールで送信する6桁のコードを生成します。
+pub fn generate_passcode() -> String {
+    let mut rng = rand::rng();
+    (0..6)
+        .map(|_| rng.random_range(0..10).to_string())
+        .collect()
+}
+
+// URLに含めても安全な、推測不可能なトークンを生成します。
+pub fn generate_secret_token() -> anyhow::Result<String> {
+    let mut buf = [0u8; 32];
+    getrandom::fill(&mut buf)?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(buf))
+}
+
+// リカバリーコードのように、十分に長いコードのハッシュです。
+pub fn hash_passcode(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+// メールで送るコードは6桁しかなく、ハッシュだけでは総当たりで戻せてしまうため、
+// 環境変数PASSCODE_SECRETを鍵にしたHMACを保存します。contextには保存先のキーを渡します。
+fn passcode_mac(context: &str, code: &str) -> anyhow::Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(env::var("PASSCODE_SECRET")?.as_bytes())?;
+    mac.update(context.as_bytes());
+    mac.update(b":");
+    mac.update(code.trim().as_bytes());
+    Ok(mac)
+}
+
+pub fn sign_passcode(context: &str, code: &str) -> anyhow::Result<String> {
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(passcode_mac(context, code)?.finalize().into_bytes()))
+}
+
+pub fn verify_passcode(context: &str, code: &str, code_hash: &str) -> anyhow::Result<bool> {
+    let Ok(expected) = BASE64_URL_SAFE_NO_PAD.decode(code_hash) else {
+        return Ok(false);
+    };
+    Ok(passcode_mac(context, code)?.verify_slice(&expected).is_ok())
+}
+
+// 失敗回数を加算し、加算後の回数を返します。
+pub async fn record_failed_attempt(
+    conn: &mut MultiplexedConnection,
+    key: &str,
+    ttl: i64,
+) -> anyhow::Result<i64> {
+    let attempts: i64 = conn.incr(key, 1).await?;
+    if attempts == 1 {
+        let _: () = conn.expire(key, ttl).await?;
+    }
+    Ok(attempts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_signed_passcode_only_in_same_context() {
+        // SAFETY: この環境変数を書き換えるテストは他にありません。
+        unsafe { env::set_var("PASSCODE_SECRET", "test-secret") };
+        let code_hash = sign_passcode("create_user:token", "123456").unwrap();
+        assert_ne!(code_hash, hash_passcode("123456"));
+        assert!(verify_passcode("create_user:token", " 123456\n", &code_hash).unwrap());
+        assert!(!verify_passcode("create_user:token", "123457", &code_hash).unwrap());
+        assert!(!verify_passcode("create_user:other", "123456", &code_hash).unwrap());
+        assert!(!verify_passcode("create_user:token", "123456", "not base64!").unwrap());
+    }
+}
@@ -0,0 +1,75 @@
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::SaltString,
+};
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+
+pub enum PasswordVerification {
+    Invalid,
+    Valid,
+    // パスワードは正しいが、古い形式で保存されているため再ハッシュが必要です。
+    ValidNeedsRehash,
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+}
+
+// アカウントがない場合にも照合する、既定のパラメーターで作ったハッシュです。
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$5zYSV753APLL0PVtSZQhGg$jYmKXFnFWabbfcbHSzFk1YwhwXaEjOMAKIR4L4va0Gk";
+
+// パスワードをArgon2idでハッシュ化し、PHC形式の文字列を返します。
+pub async fn hash_password(password: String) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let mut salt = [0u8; 16];
+        getrandom::fill(&mut salt)?;
+        let salt = SaltString::encode_b64(&salt).map_err(|e| anyhow::anyhow!(e))?;
+        let hash = argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(hash.to_string())
+    })
+    .await?
+}
+
+// 保存されているハッシュとパスワードを照合します。
+// PHC形式でないハッシュは、移行前のソルトなしSHA-256として扱います。
+pub async fn verify_password(
+    password: String,
+    password_hash: String,
+) -> anyhow::Result<PasswordVerification> {
+    tokio::task::spawn_blocking(move || {
+        if !password_hash.starts_with('$') {
+            let legacy_hash = BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(password.as_bytes()));
+            return Ok(if legacy_hash == password_hash {
+                PasswordVerification::ValidNeedsRehash
+            } else {
+                PasswordVerification::Invalid
+            });
+        }
+
+        let parsed = PasswordHash::new(&password_hash).map_err(|e| anyhow::anyhow!(e))?;
+        if argon2()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_err()
+        {
+            return Ok(PasswordVerification::Invalid);
+        }
+
+        let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&parsed).map_or(true, |params| params != Params::default());
+        Ok(if outdated {
+            PasswordVerification::ValidNeedsRehash
+        } else {
+            PasswordVerification::Valid
+        })
+    })
+    .await?
+}
+
+// 存在しないアカウントへのログインでも同じだけ時間がかかるよう、ダミーのハッシュと照合します。
+pub async fn verify_dummy_password(password: String) -> anyhow::Result<()> {
+    verify_password(password, DUMMY_PASSWORD_HASH.to_string()).await?;
+    Ok(())
+}
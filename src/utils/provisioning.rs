@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use bb8_redis::{RedisConnectionManager, bb8, redis::AsyncCommands};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    db::server_job::{claim_server_job, complete_server_job, fail_server_job, retry_server_job},
+    state::AppState,
+    utils::api::domain::{
+        self, CREATE_DOMAIN_TIMEOUT, CreateDomainRequest, DomainUnavailable, create_domain,
+    },
+};
+
+// 実行できるジョブがないときに、次に確認するまで待つ時間です。
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// この時間を過ぎても終わらないジョブは、ワーカーが止まったとみなして再実行します。
+const JOB_LEASE: Duration = Duration::from_secs(600);
+const MAX_ATTEMPTS: i32 = 5;
+const RETRY_BASE_DELAY: f64 = 30.0;
+const RETRY_MAX_DELAY: f64 = 600.0;
+// 再試行をすべて終えるまで、パスワードを残しておく秒数です。
+const SERVER_PASSWORD_TTL: u64 = 7200;
+
+// 処理中のジョブを、別のワーカーが取り出さないようにします。
+const _: () = assert!(CREATE_DOMAIN_TIMEOUT.as_secs() < JOB_LEASE.as_secs());
+
+// ジョブに保存する作成依頼です。パスワードは含めず、Redisに期限付きで保存します。
+#[derive(Serialize, Deserialize)]
+pub struct ServerJobPayload {
+    pub request: CreateDomainRequest,
+    pub has_password: bool,
+}
+
+fn server_password_key(server_id: &str) -> String {
+    format!("server_password:{server_id}")
+}
+
+pub async fn store_server_password(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    server_id: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let _: () = conn
+        .set_ex(
+            server_password_key(server_id),
+            password,
+            SERVER_PASSWORD_TTL,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn get_server_password(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    server_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut conn = pool.get().await?;
+    Ok(conn.get(server_password_key(server_id)).await?)
+}
+
+async fn discard_server_password(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    server_id: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let _: () = conn.del(server_password_key(server_id)).await?;
+    Ok(())
+}
+
+// UUIDv4の形式で、サーバーのIDを生成します。
+pub fn generate_server_id() -> anyhow::Result<String> {
+    let mut buf = [0u8; 16];
+    getrandom::fill(&mut buf)?;
+    buf[6] = (buf[6] & 0x0f) | 0x40;
+    buf[8] = (buf[8] & 0x3f) | 0x80;
+    let hex: String = buf.iter().map(|b| format!("{b:02x}")).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+// 失敗するたびに待ち時間を倍にします。
+fn retry_delay(attempts: i32) -> f64 {
+    (RETRY_BASE_DELAY * 2f64.powi(attempts - 1)).min(RETRY_MAX_DELAY)
+}
+
+// コントローラーが依頼を処理していないと分かるエラーのみ再試行します。
+// それ以外は作成が進んでいるかもしれないため、失敗にして管理者が確認します。
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.is::<DomainUnavailable>()
+        || e.downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect())
+}
+
+// サーバーの作成を依頼するジョブを取り出し、順に処理し続けます。
+pub async fn run_provisioning_worker(state: AppState) {
+    loop {
+        match process_next_job(&state).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => tracing::error!("Failed to process server job: {}", e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// ジョブを1つ処理します。実行できるジョブがなかった場合はfalseを返します。
+async fn process_next_job(state: &AppState) -> anyhow::Result<bool> {
+    let pool: &PgPool = &state.db_pool;
+    let Some(job) = claim_server_job(pool, JOB_LEASE.as_secs_f64()).await? else {
+        return Ok(false);
+    };
+    let Some(payload) = job
+        .payload
+        .as_deref()
+        .and_then(|payload| serde_json::from_str::<ServerJobPayload>(payload).ok())
+    else {
+        fail_server_job(pool, job.id, "Invalid job payload").await?;
+        discard_server_password(&state.redis_pool, &job.server_id).await?;
+        return Ok(true);
+    };
+    let mut request = payload.request;
+    if payload.has_password {
+        request.password = get_server_password(&state.redis_pool, &job.server_id).await?;
+        if request.password.is_none() {
+            fail_server_job(pool, job.id, "Server password has expired").await?;
+            return Ok(true);
+        }
+    }
+    match create_domain(&job.server_id, &request).await {
+        Ok(domain_id) => {
+            // 作成中にサーバーが削除された場合は、作成したサーバーを消して取り残さないようにします。
+            if !complete_server_job(pool, job.id, &domain_id).await? {
+                domain::delete_server(domain_id).await?;
+            }
+        }
+        Err(e) if is_transient(&e) && job.attempts < MAX_ATTEMPTS => {
+            tracing::warn!(
+                "Failed to create server {} (attempt {}): {}",
+                job.server_id,
+                job.attempts,
+                e
+            );
+            retry_server_job(pool, job.id, retry_delay(job.attempts), &e.to_string()).await?;
+            return Ok(true);
+        }
+        Err(e) => {
+            tracing::error!("Gave up creating server {}: {}", job.server_id, e);
+            fail_server_job(pool, job.id, &e.to_string()).await?;
+        }
+    }
+    discard_server_password(&state.redis_pool, &job.server_id).await?;
+    Ok(true)
+}
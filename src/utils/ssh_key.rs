@@ -0,0 +1,107 @@
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+
+// 受け付ける公開鍵の種類です。
+const SUPPORTED_KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+pub struct SshPublicKey {
+    // コメントを除いた、authorized_keysに書き込む形式です。
+    pub public_key: String,
+    pub key_type: String,
+    pub comment: Option<String>,
+    pub fingerprint: String,
+}
+
+// OpenSSH形式の公開鍵("種類 Base64 コメント")を検証します。
+// 鍵本体の先頭に書かれている種類が、指定された種類と一致しない場合も不正とします。
+pub fn parse_ssh_public_key(input: &str) -> Option<SshPublicKey> {
+    let mut parts = input.split_whitespace();
+    let key_type = parts.next()?;
+    let encoded = parts.next()?;
+    let comment = parts.collect::<Vec<_>>().join(" ");
+    if !SUPPORTED_KEY_TYPES.contains(&key_type) {
+        return None;
+    }
+    let blob = BASE64_STANDARD.decode(encoded).ok()?;
+    let name_len = u32::from_be_bytes(blob.get(..4)?.try_into().ok()?) as usize;
+    if blob.get(4..4 + name_len)? != key_type.as_bytes() || blob.len() <= 4 + name_len {
+        return None;
+    }
+    Some(SshPublicKey {
+        public_key: format!("{key_type} {encoded}"),
+        key_type: key_type.to_string(),
+        comment: (!comment.is_empty()).then_some(comment),
+        fingerprint: format!(
+            "SHA256:{}",
+            BASE64_STANDARD_NO_PAD.encode(Sha256::digest(&blob))
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIC6UKUoMP5/8rXwqU/9DnWzJp47CkFSSo/nPunI/+lM3";
+    const RSA_KEY: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQCp6yb4eoJo5mbcHw3OKbJ+AQj5lEunLeJe7WCi3nPsWVRoZuhZuvg11UN36qQkdk19H7Yn57XRlIihtTFjiQvOupnMFZtaTmWrfkShj8wYAKbYsGpFnOx4AC18Q/QI3jilavKIfVqznfzwD1sTXbK6pBCcDb2zZCa/VanSNYZDfw==";
+
+    #[test]
+    fn parses_ed25519_key_with_comment() {
+        let key = parse_ssh_public_key(&format!("{ED25519_KEY} alice@example")).unwrap();
+        assert_eq!(key.public_key, ED25519_KEY);
+        assert_eq!(key.key_type, "ssh-ed25519");
+        assert_eq!(key.comment.as_deref(), Some("alice@example"));
+        // ssh-keygen -lfの結果と一致します。
+        assert_eq!(
+            key.fingerprint,
+            "SHA256:J1XBiRdrgGvgNR1nzuwRgk5pGPz9nLBVmw2tZ77B0/Q"
+        );
+    }
+
+    #[test]
+    fn parses_rsa_key_without_comment() {
+        let key = parse_ssh_public_key(&format!("  {RSA_KEY}\n")).unwrap();
+        assert_eq!(key.public_key, RSA_KEY);
+        assert_eq!(key.key_type, "ssh-rsa");
+        assert_eq!(key.comment, None);
+        assert_eq!(
+            key.fingerprint,
+            "SHA256:W7TcJ5UpmAGLPYag0QZKYA3A2OacvNl5gyUyrqIdGFw"
+        );
+    }
+
+    #[test]
+    fn joins_comment_words() {
+        let key = parse_ssh_public_key(&format!("{ED25519_KEY} work  laptop")).unwrap();
+        assert_eq!(key.comment.as_deref(), Some("work laptop"));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert!(parse_ssh_public_key("").is_none());
+        assert!(parse_ssh_public_key("ssh-ed25519").is_none());
+        assert!(parse_ssh_public_key("ssh-ed25519 not*base64").is_none());
+        assert!(parse_ssh_public_key("ssh-dss AAAAB3NzaC1kc3M=").is_none());
+        // 種類と鍵本体が一致しません。
+        let mismatched = ED25519_KEY.replace("ssh-ed25519", "ssh-rsa");
+        assert!(parse_ssh_public_key(&mismatched).is_none());
+        // 種類の後に鍵のデータがありません。
+        assert!(parse_ssh_public_key("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5").is_none());
+    }
+
+    #[test]
+    fn rejects_option_prefixed_keys() {
+        assert!(parse_ssh_public_key(&format!("no-pty {ED25519_KEY}")).is_none());
+        assert!(parse_ssh_public_key(&format!("from=\"10.0.0.1\" {ED25519_KEY}")).is_none());
+        assert!(parse_ssh_public_key(&format!("command=\"/bin/sh\",no-pty {RSA_KEY}")).is_none());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bb8_redis::{RedisConnectionManager, bb8, redis::AsyncCommands};
+
+const DEFAULT_TOKEN_CACHE_TTL: i64 = 60;
+
+// 検証済みトークンをキャッシュしておく秒数です。
+fn token_cache_ttl() -> i64 {
+    env::var("TOKEN_CACHE_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_TOKEN_CACHE_TTL)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// ユーザーごとにハッシュを作り、nonceをフィールド、有効期限(UNIX時間)を値として保存します。
+// ユーザー単位での無効化はキーを消すだけで済みます。
+fn cache_key(user_id: i32) -> String {
+    format!("token_cache:{user_id}")
+}
+
+pub async fn is_token_cached(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    user_id: i32,
+    nonce: &str,
+) -> anyhow::Result<bool> {
+    let mut conn = pool.get().await?;
+    let expires_at: Option<i64> = conn.hget(cache_key(user_id), nonce).await?;
+    Ok(expires_at.is_some_and(|expires_at| expires_at > now()))
+}
+
+pub async fn cache_token(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    user_id: i32,
+    nonce: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let ttl = token_cache_ttl();
+    let key = cache_key(user_id);
+    let _: () = conn.hset(&key, nonce, now() + ttl).await?;
+    let _: () = conn.expire(&key, ttl).await?;
+    Ok(())
+}
+
+pub async fn invalidate_token(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    user_id: i32,
+    nonce: &str,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let _: () = conn.hdel(cache_key(user_id), nonce).await?;
+    Ok(())
+}
+
+pub async fn invalidate_user_tokens(
+    pool: &bb8::Pool<RedisConnectionManager>,
+    user_id: i32,
+) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+    let _: () = conn.del(cache_key(user_id)).await?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::env;
+
+use rand::Rng;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn build_totp(secret: &str, account_name: String) -> anyhow::Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| anyhow::anyhow!("Invalid TOTP secret: {:?}", e))?;
+    let issuer = env::var("TOTP_ISSUER").unwrap_or_else(|_| "vps-user-api".to_string());
+    Ok(TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(issuer),
+        account_name,
+    )?)
+}
+
+// Base32でエンコードされた新しいシークレットを生成します。
+pub fn generate_totp_secret() -> String {
+    match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!(),
+    }
+}
+
+// 認証アプリに読み込ませるotpauth://形式のURIを返します。
+pub fn get_otpauth_url(secret: &str, account_name: String) -> anyhow::Result<String> {
+    Ok(build_totp(secret, account_name)?.get_url())
+}
+
+pub fn verify_totp_code(secret: &str, code: &str) -> anyhow::Result<bool> {
+    Ok(build_totp(secret, String::new())?.check_current(code.trim())?)
+}
+
+// 認証アプリを失くしたときに使う、使い捨てのリカバリーコードを生成します。
+pub fn generate_recovery_codes() -> Vec<String> {
+    const CHARSET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    let mut rng = rand::rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut code: String = (0..10)
+                .map(|_| CHARSET[rng.random_range(0..CHARSET.len())] as char)
+                .collect();
+            code.insert(5, '-');
+            code
+        })
+        .collect()
+}
@@ -0,0 +1,23 @@
+use std::env;
+
+use base64::prelude::*;
+use webauthn_rs::prelude::*;
+
+// 環境変数からRelying Partyの設定を読み込み、WebAuthnの検証器を作成します。
+pub fn webauthn() -> anyhow::Result<Webauthn> {
+    let rp_id = env::var("WEBAUTHN_RP_ID")?;
+    let rp_origin = Url::parse(&env::var("WEBAUTHN_RP_ORIGIN")?)?;
+    let rp_name = env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "vps-user-api".to_string());
+    Ok(WebauthnBuilder::new(&rp_id, &rp_origin)?
+        .rp_name(&rp_name)
+        .build()?)
+}
+
+// ユーザーハンドルとして使うUUIDです。ユーザーIDから決まります。
+pub fn user_handle(user_id: i32) -> Uuid {
+    Uuid::from_u128(user_id as u128)
+}
+
+pub fn encode_credential_id(credential_id: &CredentialID) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(credential_id.as_ref())
+}
@@ -8,6 +8,7 @@ def test_create_user():
     data = {
         "username": "testuser",
         "email": "test@example.com",
+        "invite_code": input("invite code: "),
     }
     response = requests.post(url, json=data)
     assert response.status_code == 200, f"Expected status code 200, got {response.status_code}"